@@ -0,0 +1,120 @@
+//! Synthesises large GameData trees for scalability testing.
+
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use module_manager_rs::file::File;
+use module_manager_rs::game_data::GameData;
+
+/// The shape of a generated GameData tree.
+#[derive(Clone, Copy, Debug)]
+pub struct Shape {
+    pub mods: usize,
+    pub parts_per_mod: usize,
+    pub patches_per_mod: usize,
+}
+
+impl Shape {
+    pub fn parts(&self) -> usize {
+        self.mods * self.parts_per_mod
+    }
+}
+
+/// Generate a GameData tree with the given shape. Every mod has a folder with one cfg file per
+/// part and one file of patches, which target parts of every mod across a mix of passes, with and
+/// without `:NEEDS`. The output is deterministic.
+pub fn generate(shape: Shape) -> GameData {
+    let mut files = vec![];
+    for mod_idx in 0..shape.mods {
+        for part_idx in 0..shape.parts_per_mod {
+            files.push(file(
+                format!("Mod{mod_idx}/Parts/part{part_idx}.cfg"),
+                part(mod_idx, part_idx),
+            ));
+        }
+        let mut patches = String::new();
+        for patch_idx in 0..shape.patches_per_mod {
+            patch(&mut patches, shape, mod_idx, patch_idx);
+        }
+        files.push(file(format!("Mod{mod_idx}/patches.cfg"), patches));
+    }
+    GameData::from_files(files)
+}
+
+fn file(path: String, contents: String) -> File<String> {
+    File::new(Arc::from(PathBuf::from(path)), contents)
+}
+
+fn part_name(mod_idx: usize, part_idx: usize) -> String {
+    format!("mod{mod_idx}_part{part_idx}")
+}
+
+fn part(mod_idx: usize, part_idx: usize) -> String {
+    format!(
+        "PART
+{{
+    name = {name}
+    module = Part
+    mass = {mass}
+    cost = {cost}
+    MODULE
+    {{
+        name = ModuleEngines
+        maxThrust = {thrust}
+        PROPELLANT
+        {{
+            name = LiquidFuel
+            ratio = 0.9
+        }}
+    }}
+    RESOURCE
+    {{
+        name = LiquidFuel
+        amount = {amount}
+        maxAmount = {amount}
+    }}
+}}
+",
+        name = part_name(mod_idx, part_idx),
+        mass = part_idx % 10,
+        cost = part_idx * 100,
+        thrust = part_idx * 7 % 1000,
+        amount = part_idx * 3 % 500,
+    )
+}
+
+fn patch(out: &mut String, shape: Shape, mod_idx: usize, patch_idx: usize) {
+    // Spread the targets of the patches over all mods.
+    let target_mod = (mod_idx + patch_idx) % shape.mods;
+    let target = part_name(target_mod, patch_idx * 31 % shape.parts_per_mod);
+    let other_mod = (mod_idx + 1) % shape.mods;
+    let header = match patch_idx % 6 {
+        0 => format!("@PART[{target}]:FOR[Mod{mod_idx}]"),
+        1 => format!("@PART[{target}]:AFTER[Mod{other_mod}]"),
+        2 => format!("@PART[{target}]:NEEDS[Mod{other_mod}]"),
+        3 => format!("@PART[{target}]:NEEDS[!Mod{other_mod}]:FINAL"),
+        4 => format!("+PART[{target}]:LAST[Mod{mod_idx}]"),
+        _ => format!("@PART:BEFORE[Mod{other_mod}]"),
+    };
+    writeln!(
+        out,
+        "{header}
+{{
+    tag{patch_idx} = patched by Mod{mod_idx}
+    @MODULE[ModuleEngines]
+    {{
+        PROPELLANT
+        {{
+            name = Oxidizer
+            ratio = 1.1
+        }}
+    }}
+    MODULE
+    {{
+        name = Module{patch_idx}
+    }}
+}}"
+    )
+    .unwrap();
+}
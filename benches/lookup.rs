@@ -0,0 +1,68 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use module_manager_rs::config_node::{ConfigKey, ConfigNode, NodeList};
+use module_manager_rs::database::Database;
+use module_manager_rs::module_manager::searcher::{Lookup, NodeStore, Searcher};
+
+fn parts(count: usize, names: &[String]) -> NodeList<'_> {
+    let path: Arc<Path> = Arc::from(Path::new("Bench/parts.cfg"));
+    names[..count]
+        .iter()
+        .map(|name| {
+            let mut part = ConfigNode::new("PART");
+            part.file_path = Some(path.clone());
+            part.keys.push(ConfigKey::new("name", name.as_str()));
+            part
+        })
+        .collect()
+}
+
+/// Find and put back one part by name, as an exact-name `@PART[name]` patch does.
+fn edit_part<'a>(store: &mut impl NodeStore<'a>, name: &'a str) {
+    let needle = |node: &ConfigNode<'a>| node.ident == "PART" && node.name_key() == Some(name);
+    let lookup = Lookup {
+        ident: "PART",
+        names: vec![name],
+    };
+    let mut searcher = Searcher::with_lookup(lookup, needle);
+    while let Some((handle, node)) = searcher.search(store).unwrap() {
+        searcher = handle.replace(store, node).unwrap();
+    }
+}
+
+fn exact_name_lookup(c: &mut Criterion) {
+    const SIZES: [usize; 3] = [100, 1_000, 10_000];
+    let names = (0..SIZES[SIZES.len() - 1])
+        .map(|idx| format!("part{idx}"))
+        .collect::<Vec<_>>();
+
+    let mut group = c.benchmark_group("exact_name_lookup");
+    for size in SIZES {
+        // Patch every 10th part.
+        let targets = names[..size].iter().step_by(10).collect::<Vec<_>>();
+
+        let mut linear = parts(size, &names);
+        group.bench_function(BenchmarkId::new("linear", size), |b| {
+            b.iter(|| {
+                for target in &targets {
+                    edit_part(&mut linear, target);
+                }
+            })
+        });
+
+        let mut indexed = Database::from(parts(size, &names));
+        group.bench_function(BenchmarkId::new("indexed", size), |b| {
+            b.iter(|| {
+                for target in &targets {
+                    edit_part(&mut indexed, target);
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, exact_name_lookup);
+criterion_main!(benches);
@@ -0,0 +1,87 @@
+mod generator;
+
+use std::path::Path;
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use generator::Shape;
+use module_manager_rs::game_data::GameData;
+
+fn fixture(name: &str) -> GameData {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join(name)
+        .join("GameData");
+    GameData::load(&path).unwrap()
+}
+
+/// The fixtures in `tests`, and generated trees of increasing size.
+fn inputs() -> Vec<(String, GameData)> {
+    let mut inputs = vec![
+        ("RO".to_owned(), fixture("RO")),
+        ("synthetic".to_owned(), fixture("synthetic")),
+    ];
+    for mods in [10, 100] {
+        let shape = Shape {
+            mods,
+            parts_per_mod: 50,
+            patches_per_mod: 50,
+        };
+        inputs.push((
+            format!("generated_{}_parts", shape.parts()),
+            generator::generate(shape),
+        ));
+    }
+    inputs
+}
+
+fn pipeline(c: &mut Criterion) {
+    let inputs = inputs();
+
+    let mut group = c.benchmark_group("parse");
+    group.sample_size(10);
+    for (name, game_data) in &inputs {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(name),
+            game_data,
+            |b, game_data| b.iter(|| game_data.parse().unwrap()),
+        );
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("extract");
+    group.sample_size(10);
+    for (name, game_data) in &inputs {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(name),
+            game_data,
+            |b, game_data| {
+                b.iter_batched(
+                    || game_data.parse().unwrap(),
+                    |raw_patches| raw_patches.extract().unwrap(),
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("execute");
+    group.sample_size(10);
+    for (name, game_data) in &inputs {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(name),
+            game_data,
+            |b, game_data| {
+                b.iter_batched(
+                    || game_data.module_manager(std::iter::empty()).unwrap(),
+                    |module_manager| module_manager.execute().unwrap(),
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, pipeline);
+criterion_main!(benches);
@@ -0,0 +1,771 @@
+//! A language server for ModuleManager patch files.
+//!
+//! The GameData directory of the workspace is patched on startup, and patched again whenever a cfg
+//! file in it is saved. Open files are checked for parse errors, invalid pass specifiers and
+//! lints as they are edited. Hovering over the header of a top-level node shows the nodes that it
+//! targets in the patched database, and going to its definition opens the files that inserted
+//! them. Node types and passes of the loaded GameData are offered as completions.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::Context;
+use clap::Parser;
+use itertools::Itertools;
+use ksp_cfg_formatter::parser::{self, Document, NodeItem};
+use lsp_server::{Connection, Message, Notification, Request, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, DidSaveTextDocument,
+    PublishDiagnostics, ShowMessage,
+};
+use lsp_types::request::{Completion, GotoDefinition, HoverRequest};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionOptions, CompletionParams, CompletionResponse,
+    CompletionTextEdit, DiagnosticSeverity, GotoDefinitionParams, GotoDefinitionResponse, Hover,
+    HoverContents, HoverParams, HoverProviderCapability, InitializeParams, Location, MarkupContent,
+    MarkupKind, MessageType, OneOf, Position, PublishDiagnosticsParams, Range, ServerCapabilities,
+    ShowMessageParams, TextDocumentPositionParams, TextDocumentSyncCapability,
+    TextDocumentSyncKind, TextDocumentSyncOptions, TextDocumentSyncSaveOptions, TextEdit, Url,
+};
+use module_manager_rs::config_node::ConfigNode;
+use module_manager_rs::database::Database;
+use module_manager_rs::diff::parent_url;
+use module_manager_rs::file::File;
+use module_manager_rs::game_data::{GameData, LoadError};
+use module_manager_rs::location;
+use module_manager_rs::module_manager::operator::needs::Installed;
+use module_manager_rs::module_manager::{patcher, ModuleManager};
+use module_manager_rs::node_patch::NodePatch;
+use module_manager_rs::operation::Op;
+use module_manager_rs::pass::{Pass, PassIdentifier};
+use module_manager_rs::raw_patch::RawPatches;
+use module_manager_rs::validate::{self, Severity};
+use module_manager_rs::writer::Writer;
+
+/// Hovering over a patch with a wildcard could otherwise show the entire database.
+const MAX_HOVER_NODES: usize = 5;
+
+/// Speaks the language server protocol over stdin and stdout.
+#[derive(Parser, Debug)]
+#[command()]
+struct Arguments {
+    /// The GameData directory. Defaults to `GameData` in the workspace, or the workspace itself.
+    #[arg(long)]
+    game_data: Option<PathBuf>,
+    /// Register an additional installed mod, as a DLL would. May be given multiple times.
+    #[arg(long = "mod")]
+    mods: Vec<String>,
+    /// The most detailed level of log messages to print to stderr.
+    #[arg(long, default_value = "info")]
+    log_level: log::LevelFilter,
+}
+
+struct Server {
+    connection: Connection,
+    game_data: PathBuf,
+    mods: Vec<String>,
+    /// `None` until GameData has been patched without errors.
+    patched: Option<Patched>,
+    /// The contents of open files, which may differ from the saved ones.
+    open: HashMap<Url, String>,
+}
+
+/// GameData as of the last time it was patched without errors. It owns everything it keeps, so
+/// that the files it was patched from are freed; a save patches everything again.
+struct Patched {
+    database: Database<'static>,
+    mods: Vec<String>,
+    /// Every directory in GameData, relative to it.
+    directories: Vec<PathBuf>,
+    /// The mods declared by a `:FOR` pass, by the file that declares them.
+    declared: BTreeMap<Arc<Path>, Vec<String>>,
+}
+
+impl Patched {
+    fn load(game_data: &Path, mods: &[String]) -> Result<Self, LoadError> {
+        let game_data = GameData::load(game_data)?;
+        let raw = game_data.parse()?;
+        let declared = raw
+            .files
+            .iter()
+            .map(|file| {
+                let declared = top_level_nodes(&file.contents)
+                    .filter_map(|node| match node.pass {
+                        parser::Pass::For(name) => Some(name.to_owned()),
+                        _ => None,
+                    })
+                    .collect();
+                (file.path.clone(), declared)
+            })
+            .collect();
+        let database = ModuleManager::new(
+            raw,
+            mods.iter().map(String::as_str),
+            game_data.directories(),
+        )?
+        .execute()?
+        .into_owned();
+        Ok(Self {
+            database,
+            mods: mods.to_vec(),
+            directories: game_data.directories().map(Path::to_path_buf).collect(),
+            declared,
+        })
+    }
+
+    /// The mod list, without the mods that are only declared by a `:FOR` pass.
+    fn installed(&self) -> Installed<'_> {
+        Installed::new(
+            self.mods.iter().map(String::as_str),
+            self.directories.iter().map(PathBuf::as_path),
+        )
+    }
+
+    /// The mods declared by a `:FOR` pass in files other than `path`.
+    fn declared_outside<'b>(&'b self, path: &'b Path) -> impl Iterator<Item = &'b str> {
+        self.declared
+            .iter()
+            .filter(move |(other, _)| &***other != path)
+            .flat_map(|(_, declared)| declared.iter().map(String::as_str))
+    }
+}
+
+fn capabilities() -> ServerCapabilities {
+    ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Options(
+            TextDocumentSyncOptions {
+                open_close: Some(true),
+                change: Some(TextDocumentSyncKind::FULL),
+                save: Some(TextDocumentSyncSaveOptions::Supported(true)),
+                ..Default::default()
+            },
+        )),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        definition_provider: Some(OneOf::Left(true)),
+        completion_provider: Some(CompletionOptions {
+            trigger_characters: Some(vec![":".to_owned()]),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+/// `GameData` inside of the workspace if it exists, as in a KSP installation, or else the
+/// workspace itself.
+fn workspace_game_data(params: &InitializeParams) -> Option<PathBuf> {
+    #[allow(deprecated)]
+    let uri = params
+        .workspace_folders
+        .as_ref()
+        .and_then(|folders| folders.first())
+        .map(|folder| &folder.uri)
+        .or(params.root_uri.as_ref())?;
+    let root = uri.to_file_path().ok()?;
+    let game_data = root.join("GameData");
+    Some(if game_data.is_dir() { game_data } else { root })
+}
+
+impl Server {
+    fn run(&mut self) -> anyhow::Result<()> {
+        let receiver = self.connection.receiver.clone();
+        for message in &receiver {
+            match message {
+                Message::Request(request) => {
+                    if self.connection.handle_shutdown(&request)? {
+                        return Ok(());
+                    }
+                    self.handle_request(request);
+                }
+                Message::Notification(notification) => self.handle_notification(notification),
+                Message::Response(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_request(&self, request: Request) {
+        use lsp_types::request::Request as _;
+
+        let id = request.id.clone();
+        let response = match request.method.as_str() {
+            HoverRequest::METHOD => {
+                self.respond::<HoverRequest>(request, |params| self.hover(params))
+            }
+            GotoDefinition::METHOD => {
+                self.respond::<GotoDefinition>(request, |params| self.definition(params))
+            }
+            Completion::METHOD => {
+                self.respond::<Completion>(request, |params| self.completion(params))
+            }
+            _ => Response::new_err(
+                id,
+                lsp_server::ErrorCode::MethodNotFound as i32,
+                format!("unsupported request `{}`", request.method),
+            ),
+        };
+        self.send(response.into());
+    }
+
+    fn respond<R: lsp_types::request::Request>(
+        &self,
+        request: Request,
+        handler: impl FnOnce(R::Params) -> R::Result,
+    ) -> Response {
+        let id = request.id.clone();
+        match request.extract::<R::Params>(R::METHOD) {
+            Ok((id, params)) => Response::new_ok(id, handler(params)),
+            Err(e) => Response::new_err(
+                id,
+                lsp_server::ErrorCode::InvalidParams as i32,
+                format!("{e:?}"),
+            ),
+        }
+    }
+
+    fn handle_notification(&mut self, notification: Notification) {
+        use lsp_types::notification::Notification as _;
+
+        match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                if let Some(params) = extract::<DidOpenTextDocument>(notification) {
+                    let uri = params.text_document.uri;
+                    self.open.insert(uri.clone(), params.text_document.text);
+                    self.publish_diagnostics(&uri);
+                }
+            }
+            DidChangeTextDocument::METHOD => {
+                if let Some(params) = extract::<DidChangeTextDocument>(notification) {
+                    let uri = params.text_document.uri;
+                    // Changes are synchronized in full, so the last one is the entire file.
+                    if let Some(change) = params.content_changes.into_iter().last() {
+                        self.open.insert(uri.clone(), change.text);
+                    }
+                    self.publish_diagnostics(&uri);
+                }
+            }
+            DidSaveTextDocument::METHOD => {
+                if let Some(params) = extract::<DidSaveTextDocument>(notification) {
+                    // Files outside of GameData are left as full paths.
+                    let in_game_data = self.path(&params.text_document.uri).is_some_and(|path| {
+                        path.is_relative()
+                            && path
+                                .extension()
+                                .is_some_and(|extension| extension.eq_ignore_ascii_case("cfg"))
+                    });
+                    if in_game_data {
+                        self.update();
+                    }
+                }
+            }
+            DidCloseTextDocument::METHOD => {
+                if let Some(params) = extract::<DidCloseTextDocument>(notification) {
+                    let uri = params.text_document.uri;
+                    self.open.remove(&uri);
+                    self.publish_diagnostics(&uri);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn send(&self, message: Message) {
+        // A disconnected client is noticed by the main loop.
+        let _ = self.connection.sender.send(message);
+    }
+
+    fn notify<N: lsp_types::notification::Notification>(&self, params: N::Params) {
+        self.send(Notification::new(N::METHOD.to_owned(), params).into());
+    }
+
+    /// Patch GameData again. If that fails, the last successful result is kept.
+    fn update(&mut self) {
+        match Patched::load(&self.game_data, &self.mods) {
+            Ok(patched) => self.patched = Some(patched),
+            Err(e) => {
+                log::error!("{e}");
+                self.notify::<ShowMessage>(ShowMessageParams {
+                    typ: MessageType::ERROR,
+                    message: e.to_string(),
+                });
+            }
+        }
+        // Lints depend on the mod list, which may have changed.
+        for uri in self.open.keys() {
+            self.publish_diagnostics(uri);
+        }
+    }
+
+    /// The path of `uri` relative to GameData, or the full path of files outside of it.
+    fn path(&self, uri: &Url) -> Option<Arc<Path>> {
+        let path = uri.to_file_path().ok()?;
+        let path = path.canonicalize().unwrap_or(path);
+        Some(Arc::from(
+            path.strip_prefix(&self.game_data).unwrap_or(&path),
+        ))
+    }
+
+    /// The contents of the file at `path`, relative to GameData, preferring an open version.
+    fn source(&self, path: &Path) -> Option<(Url, String)> {
+        let full_path = self.game_data.join(path);
+        let uri = Url::from_file_path(&full_path).ok()?;
+        let source = match self.open.get(&uri) {
+            Some(source) => source.clone(),
+            None => std::fs::read_to_string(&full_path).ok()?,
+        };
+        Some((uri, source))
+    }
+
+    fn publish_diagnostics(&self, uri: &Url) {
+        let diagnostics = match (self.open.get(uri), self.path(uri)) {
+            (Some(source), Some(path)) => self.diagnostics(path, source),
+            _ => vec![],
+        };
+        self.notify::<PublishDiagnostics>(PublishDiagnosticsParams {
+            uri: uri.clone(),
+            diagnostics,
+            version: None,
+        });
+    }
+
+    fn diagnostics(&self, path: Arc<Path>, source: &str) -> Vec<lsp_types::Diagnostic> {
+        let document = match ksp_cfg_formatter::parse_to_ast(source) {
+            Ok(document) => document,
+            // The error carries no position, so it is shown at the start of the file.
+            Err(e) => {
+                return vec![diagnostic(
+                    Range::default(),
+                    Severity::Error,
+                    None,
+                    e.to_string(),
+                )]
+            }
+        };
+
+        let mut diagnostics = vec![];
+        for node in top_level_nodes(&document) {
+            for found in validate::validate_top_level(&path, node) {
+                diagnostics.push(diagnostic(
+                    range_of(source, found.anchor),
+                    found.severity,
+                    None,
+                    found.kind.to_string(),
+                ));
+            }
+        }
+
+        // Lints depend on the mod list, which is only known once GameData has been loaded.
+        let Some(patched) = &self.patched else {
+            return diagnostics;
+        };
+        let declared = patched.declared_outside(&path).map(PassIdentifier::from);
+        let raw = RawPatches {
+            files: vec![File::new(path.clone(), document)],
+        };
+        for lint in raw.lint_part(&patched.installed(), declared) {
+            diagnostics.push(diagnostic(
+                range_of(source, lint.anchor),
+                lint.severity,
+                Some(lint.rule.id()),
+                lint.message,
+            ));
+        }
+        diagnostics
+    }
+
+    /// The nodes of the patched database that the top-level node whose header is at `position`
+    /// targets, or that it inserted.
+    fn targets(&self, params: &TextDocumentPositionParams) -> Option<Vec<&ConfigNode<'static>>> {
+        let database = &self.patched.as_ref()?.database;
+        let source = self.open.get(&params.text_document.uri)?;
+        targets(database, source, params.position.line)
+    }
+
+    fn hover(&self, params: HoverParams) -> Option<Hover> {
+        let targets = self.targets(&params.text_document_position_params)?;
+        let mut value = match targets.len() {
+            1 => "1 node in the patched database\n".to_owned(),
+            count => format!("{count} nodes in the patched database\n"),
+        };
+        let writer = Writer::default();
+        for target in targets.iter().take(MAX_HOVER_NODES) {
+            value += "```cfg\n";
+            if let Some(path) = &target.file_path {
+                value += &format!("// {}\n", parent_url(path));
+            }
+            match writer.node_to_string(target) {
+                Ok(cfg) => value += &cfg,
+                Err(e) => value += &format!("// {e}\n"),
+            }
+            value += "```\n";
+        }
+        if targets.len() > MAX_HOVER_NODES {
+            value += &format!("and {} more\n", targets.len() - MAX_HOVER_NODES);
+        }
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value,
+            }),
+            range: None,
+        })
+    }
+
+    /// The insertion nodes in the original files that the targets of the node at the position
+    /// descend from.
+    fn definition(&self, params: GotoDefinitionParams) -> Option<GotoDefinitionResponse> {
+        let targets = self.targets(&params.text_document_position_params)?;
+        let locations = targets
+            .into_iter()
+            .filter_map(|target| {
+                let (uri, source) = self.source(target.file_path.as_deref()?)?;
+                let range = ksp_cfg_formatter::parse_to_ast(&source)
+                    .ok()
+                    .and_then(|document| {
+                        top_level_nodes(&document)
+                            .find(|node| {
+                                validate::operation(node) == Op::Insert
+                                    && node.identifier == target.ident
+                                    && name_key(node) == target.name_key()
+                            })
+                            .map(|node| range_of(&source, node.identifier))
+                    })
+                    // E.g. a copy, which keeps the path of the node it was copied from.
+                    .unwrap_or_default();
+                Some(Location { uri, range })
+            })
+            .collect();
+        Some(GotoDefinitionResponse::Array(locations))
+    }
+
+    /// Passes after a `:`, and node types of the patched database otherwise.
+    fn completion(&self, params: CompletionParams) -> Option<CompletionResponse> {
+        let patched = self.patched.as_ref()?;
+        let TextDocumentPositionParams {
+            text_document,
+            position,
+        } = params.text_document_position;
+        let source = self.open.get(&text_document.uri)?;
+        let line_start = offset(source, Position::new(position.line, 0));
+        let prefix = &source[line_start..offset(source, position)];
+
+        let (start, items) = match completing(prefix)? {
+            (start, Completing::Pass) => {
+                let mut mods = patched.installed().mods;
+                mods.extend(
+                    patched
+                        .declared
+                        .values()
+                        .flatten()
+                        .map(|name| PassIdentifier::from(name.as_str())),
+                );
+                let mut passes = vec![Pass::First, Pass::Final];
+                for ident in mods.into_iter().sorted() {
+                    passes.extend([
+                        Pass::Before(ident.clone()),
+                        Pass::For(ident.clone()),
+                        Pass::After(ident.clone()),
+                        Pass::Last(ident),
+                    ]);
+                }
+                let passes = passes
+                    .into_iter()
+                    .map(|pass| (pass.to_string(), CompletionItemKind::EVENT))
+                    .collect::<Vec<_>>();
+                (start, passes)
+            }
+            (start, Completing::NodeType) => {
+                let mut idents = BTreeSet::new();
+                for node in patched.database.nodes() {
+                    collect_idents(node, &mut idents);
+                }
+                let idents = idents
+                    .into_iter()
+                    .map(|ident| (ident.to_owned(), CompletionItemKind::CLASS))
+                    .collect();
+                (start, idents)
+            }
+        };
+
+        let range = Range::new(position_of(source, line_start + start), position);
+        let items = items
+            .into_iter()
+            .map(|(label, kind)| CompletionItem {
+                text_edit: Some(CompletionTextEdit::Edit(TextEdit::new(
+                    range,
+                    label.clone(),
+                ))),
+                label,
+                kind: Some(kind),
+                ..Default::default()
+            })
+            .collect();
+        Some(CompletionResponse::Array(items))
+    }
+}
+
+/// Operators that may precede the node type of a patch.
+const OPERATORS: &[char] = &['@', '%', '&', '+', '$', '-', '!', '|', '#', '*'];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Completing {
+    Pass,
+    NodeType,
+}
+
+/// What the word at the end of `prefix`, the line up to the cursor, completes to, and the offset
+/// within `prefix` where the completed text starts: passes after a `:`, and node types after the
+/// operators of a patch. Names and the values of keys are not completed.
+fn completing(prefix: &str) -> Option<(usize, Completing)> {
+    let word = prefix.trim_end_matches(|c: char| !c.is_whitespace()).len();
+    if let Some(colon) = prefix[word..].rfind(':') {
+        Some((word + colon, Completing::Pass))
+    } else if prefix[word..].contains('[') || prefix.contains('=') {
+        None
+    } else {
+        let operators = prefix[word..].len() - prefix[word..].trim_start_matches(OPERATORS).len();
+        Some((word + operators, Completing::NodeType))
+    }
+}
+
+/// The nodes of `database` that the top-level node of `source` whose header is on `line` targets,
+/// or that it inserted.
+fn targets<'d>(
+    database: &'d Database<'static>,
+    source: &str,
+    line: u32,
+) -> Option<Vec<&'d ConfigNode<'static>>> {
+    let document = ksp_cfg_formatter::parse_to_ast(source).ok()?;
+    let node = top_level_nodes(&document)
+        .find(|node| range_of(source, node.identifier).start.line == line)?;
+
+    let targets = if validate::operation(node) == Op::Insert {
+        let name = name_key(node);
+        database
+            .nodes()
+            .iter()
+            .filter(|target| target.ident == node.identifier && target.name_key() == name)
+            .collect()
+    } else {
+        let patch = NodePatch::from_cst(node.clone(), true).ok()?;
+        database
+            .nodes()
+            .iter()
+            .filter(|target| patcher::matches(&patch, target))
+            .collect()
+    };
+    Some(targets)
+}
+
+fn collect_idents<'b>(node: &'b ConfigNode, idents: &mut BTreeSet<&'b str>) {
+    idents.insert(&node.ident);
+    for child in &node.nodes {
+        collect_idents(child, idents);
+    }
+}
+
+fn extract<N: lsp_types::notification::Notification>(
+    notification: Notification,
+) -> Option<N::Params> {
+    match notification.extract::<N::Params>(N::METHOD) {
+        Ok(params) => Some(params),
+        Err(e) => {
+            log::warn!("malformed notification: {e:?}");
+            None
+        }
+    }
+}
+
+fn diagnostic(
+    range: Range,
+    severity: Severity,
+    code: Option<&str>,
+    message: String,
+) -> lsp_types::Diagnostic {
+    lsp_types::Diagnostic {
+        range,
+        severity: Some(match severity {
+            Severity::Warning => DiagnosticSeverity::WARNING,
+            Severity::Error => DiagnosticSeverity::ERROR,
+        }),
+        code: code.map(|code| lsp_types::NumberOrString::String(code.to_owned())),
+        source: Some("module_manager_rs".to_owned()),
+        message,
+        ..Default::default()
+    }
+}
+
+fn top_level_nodes<'b, 'a>(
+    document: &'b Document<'a>,
+) -> impl Iterator<Item = &'b parser::Node<'a>> {
+    document.statements.iter().filter_map(|item| match item {
+        NodeItem::Node(node) => Some(node),
+        _ => None,
+    })
+}
+
+fn name_key<'a>(node: &parser::Node<'a>) -> Option<&'a str> {
+    node.block.iter().find_map(|item| match item {
+        NodeItem::KeyVal(key) if key.key == "name" => Some(key.val),
+        _ => None,
+    })
+}
+
+/// The range of `text`, which borrows from `source`, or an empty range at the start of the file if
+/// it does not. LSP positions count UTF-16 code units within a line.
+fn range_of(source: &str, text: &str) -> Range {
+    match location::offset(source, text) {
+        Some(start) => Range::new(
+            position_of(source, start),
+            position_of(source, start + text.len()),
+        ),
+        None => Range::default(),
+    }
+}
+
+/// The position of the byte `offset`, which must be at a character boundary of `source`.
+fn position_of(source: &str, offset: usize) -> Position {
+    let line_start = location::line_start(source, offset);
+    Position::new(
+        source[..offset].matches('\n').count() as u32,
+        source[line_start..offset].encode_utf16().count() as u32,
+    )
+}
+
+/// The byte offset of `position` within `source`, clamped to the end of its line.
+fn offset(source: &str, position: Position) -> usize {
+    let mut line_start = 0;
+    for _ in 0..position.line {
+        match source[line_start..].find('\n') {
+            Some(idx) => line_start += idx + 1,
+            None => return source.len(),
+        }
+    }
+    let line = source[line_start..].split('\n').next().unwrap_or_default();
+    let mut units = 0;
+    for (idx, c) in line.char_indices() {
+        if units >= position.character as usize {
+            return line_start + idx;
+        }
+        units += c.len_utf16();
+    }
+    line_start + line.len()
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Arguments::parse();
+    // Logs go to stderr, as stdout carries the protocol.
+    pretty_env_logger::formatted_builder()
+        .filter_level(args.log_level)
+        .init();
+
+    let (connection, io_threads) = Connection::stdio();
+    let capabilities = serde_json::to_value(capabilities())?;
+    let params: InitializeParams = serde_json::from_value(connection.initialize(capabilities)?)?;
+    let game_data = args
+        .game_data
+        .or_else(|| workspace_game_data(&params))
+        .context("no GameData directory was given, and the workspace is not a directory")?;
+    let game_data = game_data
+        .canonicalize()
+        .with_context(|| format!("GameData directory {game_data:?}"))?;
+    log::info!("GameData path: {game_data:?}");
+
+    let mut server = Server {
+        connection,
+        game_data,
+        mods: args.mods,
+        patched: None,
+        open: HashMap::new(),
+    };
+    server.update();
+    server.run()?;
+    drop(server);
+    io_threads.join()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positions_count_utf16_units() {
+        // `é` is 2 bytes and 1 unit, `𝄞` is 4 bytes and 2 units.
+        let source = "é𝄞x\nab";
+        assert_eq!(position_of(source, 0), Position::new(0, 0));
+        assert_eq!(position_of(source, 6), Position::new(0, 3));
+        assert_eq!(position_of(source, 7), Position::new(0, 4));
+        assert_eq!(position_of(source, 9), Position::new(1, 1));
+        assert_eq!(offset(source, Position::new(0, 3)), 6);
+        assert_eq!(offset(source, Position::new(1, 1)), 9);
+
+        // Past the end of a line, and past the last line, which has no `\n`.
+        assert_eq!(offset(source, Position::new(0, 10)), 7);
+        assert_eq!(offset(source, Position::new(1, 10)), source.len());
+        assert_eq!(offset(source, Position::new(5, 0)), source.len());
+    }
+
+    #[test]
+    fn ranges_of_anchors() {
+        let source = "PART\n{\n\tname = 𝄞a\n}";
+        let name = &source[15..20];
+        assert_eq!(name, "𝄞a");
+        assert_eq!(
+            range_of(source, name),
+            Range::new(Position::new(2, 8), Position::new(2, 11))
+        );
+        assert_eq!(
+            range_of(source, &source[source.len()..]),
+            Range::new(Position::new(3, 1), Position::new(3, 1))
+        );
+        // Text that does not borrow from the source.
+        assert_eq!(range_of(source, "PART"), Range::default());
+    }
+
+    #[test]
+    fn completes_passes_and_node_types() {
+        assert_eq!(completing(""), Some((0, Completing::NodeType)));
+        assert_eq!(completing("\t@PA"), Some((2, Completing::NodeType)));
+        assert_eq!(completing("!MOD"), Some((1, Completing::NodeType)));
+        assert_eq!(completing("@PART[a]:FO"), Some((8, Completing::Pass)));
+        assert_eq!(completing("@PART:HAS[#a]:"), Some((13, Completing::Pass)));
+        assert_eq!(completing("@PART[a"), None);
+        assert_eq!(completing("\tname = a"), None);
+        assert_eq!(completing("\tname =a"), None);
+    }
+
+    #[test]
+    fn targets_of_inserts_and_edits() {
+        let data = "PART\n{\n\tname = a\n}\nPART\n{\n\tname = b\n}\n";
+        let database = GameData::from_files(vec![File::new(
+            Arc::from(Path::new("ModA/parts.cfg")),
+            data.to_owned(),
+        )])
+        .owned_database(std::iter::empty())
+        .unwrap();
+        let names = |targets: Option<Vec<&ConfigNode>>| {
+            targets.map(|targets| {
+                targets
+                    .into_iter()
+                    .map(|target| target.name_key().unwrap().to_owned())
+                    .collect::<Vec<_>>()
+            })
+        };
+
+        assert_eq!(names(targets(&database, data, 4)), Some(vec!["b".into()]));
+        // Only the line of a header has targets.
+        assert_eq!(names(targets(&database, data, 2)), None);
+
+        let patches = "@PART[a]\n{\n\tmass = 1\n}\n@PART[*]:FINAL\n{\n}\n@PART[c]\n{\n}\n";
+        assert_eq!(
+            names(targets(&database, patches, 0)),
+            Some(vec!["a".into()])
+        );
+        assert_eq!(
+            names(targets(&database, patches, 4)),
+            Some(vec!["a".into(), "b".into()])
+        );
+        assert_eq!(names(targets(&database, patches, 7)), Some(vec![]));
+    }
+}
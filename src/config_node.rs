@@ -1,30 +1,81 @@
 use std::borrow::Cow;
 use std::fmt::{Display, Formatter};
 use std::path::Path;
-use std::rc::Rc;
+use std::sync::Arc;
 
-#[derive(Clone, PartialEq, Eq, Debug, Default)]
+use serde_json::json;
+
+use crate::module_manager::searcher::{Lookup, NodeStore};
+
+#[derive(Clone, Debug, Default)]
 
 pub struct ConfigNode<'a> {
-    pub file_path: Option<Rc<Path>>,
-    pub ident: &'a str,
+    pub file_path: Option<Arc<Path>>,
+    pub ident: Cow<'a, str>,
     pub nodes: NodeList<'a>,
     pub keys: Vec<ConfigKey<'a>>,
+    /// See [`ConfigNode::keys_before`]. Only set while reading and patching, as it describes the
+    /// node's position among its parent's keys.
+    pub(crate) keys_before: usize,
 }
 
-pub type NodeList<'a> = Vec<Option<ConfigNode<'a>>>;
+/// The child nodes of a node, or the top-level nodes of the database, in order.
+///
+/// Deleting a node leaves a tombstone in its slot, so that deleting many nodes during a single
+/// sweep does not shift the remaining nodes every time. Tombstones are dropped by
+/// [`NodeStore::compact`] once they make up most of the list.
+#[derive(Clone, Debug, Default)]
+pub struct NodeList<'a> {
+    slots: Vec<Slot<'a>>,
+    tombstones: usize,
+}
+
+#[derive(Clone, Debug)]
+enum Slot<'a> {
+    Node(ConfigNode<'a>),
+    /// The node has been taken out by a [`Searcher`](crate::module_manager::searcher::Searcher)
+    /// and is being patched.
+    Active,
+    Deleted,
+}
 
 #[derive(Clone, PartialEq, Eq, Debug, Default)]
 pub struct ConfigKey<'a> {
-    pub ident: &'a str,
+    pub ident: Cow<'a, str>,
     pub value: Cow<'a, str>,
 }
 
 impl<'a> ConfigNode<'a> {
+    /// A node without keys or children, outside of the database.
+    pub fn new(ident: impl Into<Cow<'a, str>>) -> Self {
+        Self {
+            ident: ident.into(),
+            ..Default::default()
+        }
+    }
+
+    /// The number of the parent's keys that precede this node, so that a
+    /// [`Writer`](crate::writer::Writer) can interleave keys and nodes the way they were read.
+    /// This does not take part in comparisons, as KSP keeps keys and nodes apart.
+    pub fn keys_before(&self) -> usize {
+        self.keys_before
+    }
+
     pub fn is_top_level(&self) -> bool {
         self.file_path.is_some()
     }
 
+    /// Detach this node from the buffers it borrows from.
+    pub fn into_owned(self) -> ConfigNode<'static> {
+        ConfigNode {
+            file_path: self.file_path,
+            ident: Cow::Owned(self.ident.into_owned()),
+            nodes: self.nodes.into_iter().map(ConfigNode::into_owned).collect(),
+            keys: self.keys.into_iter().map(ConfigKey::into_owned).collect(),
+            keys_before: self.keys_before,
+        }
+    }
+
     pub fn name_key(&self) -> Option<&str> {
         self.keys
             .iter()
@@ -32,6 +83,22 @@ impl<'a> ConfigNode<'a> {
             .map(|item| item.value.as_ref())
     }
 
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "ident": self.ident,
+            "keys": self
+                .keys
+                .iter()
+                .map(|key| json!([key.ident, key.value]))
+                .collect::<Vec<_>>(),
+            "nodes": self
+                .nodes
+                .iter()
+                .map(ConfigNode::to_json)
+                .collect::<Vec<_>>(),
+        })
+    }
+
     pub fn fmt_into(
         &self,
         f: &mut Formatter<'_>,
@@ -50,29 +117,213 @@ impl<'a> ConfigNode<'a> {
             key.fmt_into(f, indent + 1, indent_size)?;
         }
         for node in &self.nodes {
-            node.as_ref()
-                .unwrap()
-                .fmt_into(f, indent + 1, indent_size)?;
+            node.fmt_into(f, indent + 1, indent_size)?;
         }
         writeln!(f, "{0:1$}}}", "", indent_size * indent)?;
         Ok(())
     }
 }
 
+impl<'a> PartialEq for ConfigNode<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        self.file_path == other.file_path
+            && self.ident == other.ident
+            && self.nodes == other.nodes
+            && self.keys == other.keys
+    }
+}
+
+impl<'a> Eq for ConfigNode<'a> {}
+
 impl<'a> Display for ConfigNode<'a> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         self.fmt_into(f, 0, 4)
     }
 }
 
+impl<'a> NodeList<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of nodes in the list, excluding tombstones.
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.tombstones
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The nodes in the list, skipping tombstones and nodes that are being patched.
+    pub fn iter(&self) -> Iter<'_, 'a> {
+        Iter(self.slots.iter())
+    }
+
+    pub fn push(&mut self, node: ConfigNode<'a>) {
+        self.slots.push(Slot::Node(node));
+    }
+
+    /// Insert `node` before the slot at `idx`, moving every later slot.
+    pub fn insert(&mut self, idx: usize, node: ConfigNode<'a>) {
+        self.slots.insert(idx, Slot::Node(node));
+    }
+
+    /// The number of slots in the list, including tombstones. Positions used by [`NodeStore`]
+    /// range up to this value.
+    pub(crate) fn slot_count(&self) -> usize {
+        self.slots.len()
+    }
+}
+
+impl<'a> PartialEq for NodeList<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        self.iter().eq(other.iter())
+    }
+}
+
+impl<'a> Eq for NodeList<'a> {}
+
+impl<'a> FromIterator<ConfigNode<'a>> for NodeList<'a> {
+    fn from_iter<T: IntoIterator<Item = ConfigNode<'a>>>(iter: T) -> Self {
+        Self {
+            slots: iter.into_iter().map(Slot::Node).collect(),
+            tombstones: 0,
+        }
+    }
+}
+
+impl<'a> From<Vec<ConfigNode<'a>>> for NodeList<'a> {
+    fn from(nodes: Vec<ConfigNode<'a>>) -> Self {
+        nodes.into_iter().collect()
+    }
+}
+
+impl<'a> IntoIterator for NodeList<'a> {
+    type Item = ConfigNode<'a>;
+    type IntoIter = IntoIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter(self.slots.into_iter())
+    }
+}
+
+impl<'b, 'a> IntoIterator for &'b NodeList<'a> {
+    type Item = &'b ConfigNode<'a>;
+    type IntoIter = Iter<'b, 'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+pub struct Iter<'b, 'a>(std::slice::Iter<'b, Slot<'a>>);
+
+impl<'b, 'a> Iterator for Iter<'b, 'a> {
+    type Item = &'b ConfigNode<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.find_map(|slot| match slot {
+            Slot::Node(node) => Some(node),
+            Slot::Active | Slot::Deleted => None,
+        })
+    }
+}
+
+pub struct IntoIter<'a>(std::vec::IntoIter<Slot<'a>>);
+
+impl<'a> Iterator for IntoIter<'a> {
+    type Item = ConfigNode<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.find_map(|slot| match slot {
+            Slot::Node(node) => Some(node),
+            Slot::Active | Slot::Deleted => None,
+        })
+    }
+}
+
+impl<'a> NodeStore<'a> for NodeList<'a> {
+    fn next_candidate(&self, start: usize, _lookup: Option<&Lookup<'a>>) -> Option<usize> {
+        let offset = self
+            .slots
+            .get(start..)?
+            .iter()
+            .position(|slot| !matches!(slot, Slot::Deleted))?;
+        Some(start + offset)
+    }
+
+    fn slot(&self, idx: usize) -> Option<&ConfigNode<'a>> {
+        match &self.slots[idx] {
+            Slot::Node(node) => Some(node),
+            Slot::Active | Slot::Deleted => None,
+        }
+    }
+
+    fn take(&mut self, idx: usize) -> Option<ConfigNode<'a>> {
+        match std::mem::replace(&mut self.slots[idx], Slot::Active) {
+            Slot::Node(node) => Some(node),
+            slot => {
+                self.slots[idx] = slot;
+                None
+            }
+        }
+    }
+
+    fn replace(&mut self, idx: usize, node: ConfigNode<'a>) -> Option<ConfigNode<'a>> {
+        match std::mem::replace(&mut self.slots[idx], Slot::Node(node)) {
+            Slot::Node(previous) => Some(previous),
+            Slot::Active => None,
+            Slot::Deleted => {
+                self.tombstones -= 1;
+                None
+            }
+        }
+    }
+
+    fn remove(&mut self, idx: usize) -> Option<ConfigNode<'a>> {
+        match std::mem::replace(&mut self.slots[idx], Slot::Deleted) {
+            Slot::Node(previous) => {
+                self.tombstones += 1;
+                Some(previous)
+            }
+            Slot::Active => {
+                self.tombstones += 1;
+                None
+            }
+            Slot::Deleted => None,
+        }
+    }
+
+    fn push(&mut self, node: ConfigNode<'a>) {
+        NodeList::push(self, node);
+    }
+
+    fn compact(&mut self) -> bool {
+        if self.tombstones * 2 <= self.slots.len() {
+            return false;
+        }
+        self.slots.retain(|slot| !matches!(slot, Slot::Deleted));
+        self.tombstones = 0;
+        true
+    }
+}
+
 impl<'a> ConfigKey<'a> {
-    pub fn new(ident: &'a str, value: impl Into<Cow<'a, str>>) -> Self {
+    pub fn new(ident: impl Into<Cow<'a, str>>, value: impl Into<Cow<'a, str>>) -> Self {
         Self {
-            ident,
+            ident: ident.into(),
             value: value.into(),
         }
     }
 
+    pub fn into_owned(self) -> ConfigKey<'static> {
+        ConfigKey {
+            ident: Cow::Owned(self.ident.into_owned()),
+            value: Cow::Owned(self.value.into_owned()),
+        }
+    }
+
     pub fn fmt_into(
         &self,
         f: &mut Formatter<'_>,
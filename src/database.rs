@@ -1,36 +1,410 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
+use std::path::{Component, Path};
+use std::sync::Arc;
+
+use ksp_cfg_formatter::parser::{Document, NodeItem};
+use serde_json::json;
 
 use crate::config_node::{ConfigKey, ConfigNode, NodeList};
-use crate::{internal_error, Result};
+use crate::diff::parent_url;
+use crate::module_manager::patcher::evaluate_node_as_pure_data;
+use crate::module_manager::searcher::{Lookup, NodeStore};
+use crate::node_patch::NodePatch;
+use crate::writer::{WriteError, Writer};
+use crate::{internal_error, PatchingError, Result};
+
+/// The top-level nodes of the game database, in insertion order.
+///
+/// Top-level nodes are indexed by their type and `name`, so that patches targeting specific names
+/// do not need to scan the whole database.
+#[derive(Clone, Debug, Default)]
+pub struct Database<'a> {
+    nodes: NodeList<'a>,
+    index: NameIndex<'a>,
+}
 
+/// Positions of the top-level nodes with a `name` key, by node type and name. Nodes that are being
+/// patched are removed from the index until they are put back.
 #[derive(Clone, PartialEq, Debug, Default)]
-pub struct Database<'a>(pub NodeList<'a>);
+struct NameIndex<'a> {
+    positions: HashMap<Cow<'a, str>, HashMap<Cow<'a, str>, Vec<usize>>>,
+    /// Set when a node was found to be missing from the index, which is a bug. Lookups then scan
+    /// the whole database until the index is rebuilt by [`NodeStore::compact`].
+    stale: bool,
+}
 
 impl<'a> Database<'a> {
     pub fn insert(&mut self, top_level_node: ConfigNode<'a>) -> Result {
         if !top_level_node.is_top_level() {
             internal_error("attempted to insert top-level-node nor marked as such")?;
         }
-        self.0.push(Some(top_level_node));
+        NodeStore::push(self, top_level_node);
         Ok(())
     }
+
+    pub fn nodes(&self) -> &NodeList<'a> {
+        &self.nodes
+    }
+
+    /// Detach this database from the buffers it borrows from, so that it can be stored or sent
+    /// elsewhere.
+    pub fn into_owned(self) -> Database<'static> {
+        self.nodes.into_iter().map(ConfigNode::into_owned).collect()
+    }
+
+    /// Load the database stored in a `ModuleManager.ConfigCache`, where each top-level node is
+    /// wrapped in a `UrlConfig` node recording its `parentUrl`.
+    pub fn from_config_cache(cache: Document<'a>) -> Result<Self> {
+        let malformed = |msg: String| PatchingError::MalformedCache(msg.into());
+
+        let mut database = Self::default();
+        for item in cache.statements {
+            // The cache also contains top-level bookkeeping keys, such as `patchedNodeCount`.
+            let NodeItem::Node(url_config) = item else {
+                continue;
+            };
+            if !matches!(url_config.identifier, "UrlConfig" | "URL_CONFIG") {
+                return Err(malformed(format!(
+                    "unexpected top-level node `{}`",
+                    url_config.identifier
+                )));
+            }
+            let mut parent_url = None;
+            let mut nodes = vec![];
+            for child in url_config.block {
+                match child {
+                    NodeItem::KeyVal(key) if key.key == "parentUrl" => parent_url = Some(key.val),
+                    NodeItem::Node(node) => nodes.push(node),
+                    _ => {}
+                }
+            }
+            let parent_url =
+                parent_url.ok_or_else(|| malformed("`UrlConfig` without `parentUrl`".into()))?;
+            let path: Arc<Path> = Arc::from(Path::new(parent_url));
+            for node in nodes {
+                let mut node =
+                    evaluate_node_as_pure_data(path.clone(), &NodePatch::from_cst(node, true)?)?;
+                node.file_path = Some(path.clone());
+                database.insert(node)?;
+            }
+        }
+        Ok(database)
+    }
+
+    /// The top-level nodes as JSON, each with the `parentUrl` of its file.
+    pub fn to_json(&self) -> serde_json::Value {
+        self.nodes
+            .iter()
+            .map(|node| {
+                let mut value = node.to_json();
+                if let Some(path) = &node.file_path {
+                    value["parentUrl"] = json!(parent_url(path));
+                }
+                value
+            })
+            .collect()
+    }
+
+    /// The top-level nodes grouped by their file, with files in the order of their first node.
+    /// Nodes keep their file when they are copied, and nodes inserted by a patch belong to the
+    /// patch's file.
+    pub fn files(&self) -> Vec<(&Arc<Path>, Vec<&ConfigNode<'a>>)> {
+        let mut files: Vec<(&Arc<Path>, Vec<_>)> = vec![];
+        let mut positions = HashMap::new();
+        for node in &self.nodes {
+            let Some(path) = &node.file_path else {
+                continue;
+            };
+            let idx = *positions.entry(path).or_insert_with(|| {
+                files.push((path, vec![]));
+                files.len() - 1
+            });
+            files[idx].1.push(node);
+        }
+        files
+    }
+
+    /// Write the nodes of each file to the same path below `directory`, mirroring the layout of
+    /// GameData. Existing files are overwritten.
+    pub fn export(&self, directory: &Path, writer: &Writer) -> std::result::Result<(), WriteError> {
+        for (path, nodes) in self.files() {
+            if !path
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)))
+            {
+                return Err(WriteError::InvalidPath(path.to_path_buf()));
+            }
+            let mut contents = String::new();
+            writer.write_nodes(&mut contents, nodes)?;
+
+            let output = directory.join(path);
+            let io_error = |source| WriteError::Io {
+                path: output.clone(),
+                source,
+            };
+            if let Some(parent) = output.parent() {
+                std::fs::create_dir_all(parent).map_err(io_error)?;
+            }
+            std::fs::write(&output, contents).map_err(io_error)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> From<NodeList<'a>> for Database<'a> {
+    fn from(nodes: NodeList<'a>) -> Self {
+        nodes.into_iter().collect()
+    }
+}
+
+impl<'a> FromIterator<ConfigNode<'a>> for Database<'a> {
+    fn from_iter<T: IntoIterator<Item = ConfigNode<'a>>>(iter: T) -> Self {
+        let nodes = iter.into_iter().collect();
+        Self {
+            index: NameIndex::build(&nodes),
+            nodes,
+        }
+    }
+}
+
+impl<'a> PartialEq for Database<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        // The index is derived from the nodes, but depends on the positions of tombstones.
+        self.nodes == other.nodes
+    }
+}
+
+impl<'a> NodeStore<'a> for Database<'a> {
+    fn next_candidate(&self, start: usize, lookup: Option<&Lookup<'a>>) -> Option<usize> {
+        match lookup {
+            Some(lookup) if !self.index.stale => lookup
+                .names
+                .iter()
+                .filter_map(|name| self.index.next(start, lookup.ident, name))
+                .min(),
+            _ => self.nodes.next_candidate(start, None),
+        }
+    }
+
+    fn slot(&self, idx: usize) -> Option<&ConfigNode<'a>> {
+        self.nodes.slot(idx)
+    }
+
+    fn take(&mut self, idx: usize) -> Option<ConfigNode<'a>> {
+        let node = self.nodes.take(idx)?;
+        self.index.remove(idx, &node);
+        Some(node)
+    }
+
+    fn replace(&mut self, idx: usize, node: ConfigNode<'a>) -> Option<ConfigNode<'a>> {
+        self.index.add(idx, &node);
+        let previous = self.nodes.replace(idx, node);
+        if let Some(previous) = &previous {
+            self.index.remove(idx, previous);
+        }
+        previous
+    }
+
+    fn remove(&mut self, idx: usize) -> Option<ConfigNode<'a>> {
+        let previous = self.nodes.remove(idx);
+        if let Some(previous) = &previous {
+            self.index.remove(idx, previous);
+        }
+        previous
+    }
+
+    fn push(&mut self, node: ConfigNode<'a>) {
+        self.index.add(self.nodes.slot_count(), &node);
+        self.nodes.push(node);
+    }
+
+    fn compact(&mut self) -> bool {
+        let moved = self.nodes.compact();
+        if moved || self.index.stale {
+            self.index = NameIndex::build(&self.nodes);
+        }
+        moved
+    }
+}
+
+impl<'a> NameIndex<'a> {
+    /// Index a list without active slots.
+    fn build(nodes: &NodeList<'a>) -> Self {
+        let mut index = Self::default();
+        for idx in 0..nodes.slot_count() {
+            if let Some(node) = nodes.slot(idx) {
+                index.add(idx, node);
+            }
+        }
+        index
+    }
+
+    fn add(&mut self, idx: usize, node: &ConfigNode<'a>) {
+        let Some(name) = name_of(node) else {
+            return;
+        };
+        let positions = self
+            .positions
+            .entry(node.ident.clone())
+            .or_default()
+            .entry(name.clone())
+            .or_default();
+        let at = positions.partition_point(|&position| position < idx);
+        positions.insert(at, idx);
+    }
+
+    fn remove(&mut self, idx: usize, node: &ConfigNode<'a>) {
+        let Some(name) = name_of(node) else {
+            return;
+        };
+        let removed = self
+            .positions
+            .get_mut(&node.ident)
+            .and_then(|names| names.get_mut(name))
+            .and_then(|positions| {
+                let at = positions.binary_search(&idx).ok()?;
+                Some(positions.remove(at))
+            });
+        debug_assert!(removed.is_some(), "named node at {idx} is not indexed");
+        if removed.is_none() {
+            log::error!("the index of the database is out of sync, falling back to scanning");
+            self.stale = true;
+        }
+    }
+
+    /// The position of the first node at or after `start` with the given type and name.
+    fn next(&self, start: usize, ident: &str, name: &str) -> Option<usize> {
+        let positions = self.positions.get(ident)?.get(name)?;
+        positions
+            .get(positions.partition_point(|&position| position < start))
+            .copied()
+    }
+}
+
+/// The value of the node's `name` key, which is what patches match against.
+fn name_of<'b, 'a>(node: &'b ConfigNode<'a>) -> Option<&'b Cow<'a, str>> {
+    node.keys
+        .iter()
+        .find(|key| key.ident == "name")
+        .map(|key| &key.value)
 }
 
 impl<'a> Display for Database<'a> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        for node in &self.0 {
-            let node = node.as_ref().unwrap();
+        for node in &self.nodes {
             let wrapper = ConfigNode {
                 file_path: None,
-                ident: "URL_CONFIG",
-                nodes: vec![Some(node.clone())],
+                ident: "URL_CONFIG".into(),
+                nodes: NodeList::from(vec![node.clone()]),
                 keys: vec![ConfigKey::new(
                     "parentUrl",
                     node.file_path.as_ref().unwrap().to_string_lossy(),
                 )],
+                keys_before: 0,
             };
             wrapper.fmt_into(f, 0, 4)?;
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use super::*;
+
+    fn part(name: &str) -> ConfigNode<'_> {
+        ConfigNode {
+            file_path: Some(Arc::from(Path::new("Mod/parts.cfg"))),
+            ident: "PART".into(),
+            nodes: NodeList::new(),
+            keys: vec![ConfigKey::new("name", name)],
+            keys_before: 0,
+        }
+    }
+
+    #[test]
+    fn index_tracks_mutations() {
+        let mut database: Database = [part("a"), part("b"), part("a")].into_iter().collect();
+        let lookup = |names| Lookup {
+            ident: "PART",
+            names,
+        };
+        let positions = |database: &Database, name| {
+            let lookup = lookup(vec![name]);
+            std::iter::successors(database.next_candidate(0, Some(&lookup)), |&idx| {
+                database.next_candidate(idx + 1, Some(&lookup))
+            })
+            .collect::<Vec<_>>()
+        };
+        assert_eq!(positions(&database, "a"), [0, 2]);
+
+        database.take(0).unwrap();
+        database.remove(0);
+        database.push(part("c"));
+        database.push(part("b"));
+        let mut renamed = database.take(2).unwrap();
+        renamed.keys[0] = ConfigKey::new("name", "c");
+        database.replace(2, renamed);
+        // Slots: tombstone, b, c, c, b
+        assert!(positions(&database, "a").is_empty());
+        assert_eq!(positions(&database, "b"), [1, 4]);
+        assert_eq!(positions(&database, "c"), [2, 3]);
+
+        for idx in [1, 4] {
+            database.take(idx).unwrap();
+            database.remove(idx);
+        }
+        assert!(database.compact());
+        assert_eq!(positions(&database, "c"), [0, 1]);
+        assert!(positions(&database, "b").is_empty());
+        assert_eq!(database, [part("c"), part("c")].into_iter().collect());
+    }
+
+    #[test]
+    fn stale_index_falls_back_to_scan() {
+        let mut database: Database = [part("a"), part("b")].into_iter().collect();
+        database.remove(0);
+        database.index.positions.clear();
+        database.index.stale = true;
+        let lookup = Lookup {
+            ident: "PART",
+            names: vec!["b"],
+        };
+        // Every slot is a candidate, and the searcher's needle checks it.
+        assert_eq!(database.next_candidate(0, Some(&lookup)), Some(1));
+        assert_eq!(database.next_candidate(2, Some(&lookup)), None);
+
+        // Rebuilding keeps positions, as half of the slots are not enough to compact.
+        assert!(!database.compact());
+        assert!(!database.index.stale);
+        assert_eq!(database.next_candidate(0, Some(&lookup)), Some(1));
+    }
+
+    #[test]
+    fn export_mirrors_files() -> std::result::Result<(), WriteError> {
+        let root = std::env::temp_dir().join(format!("mm_rs_export_{}", std::process::id()));
+        let mut other = part("b");
+        other.file_path = Some(Arc::from(Path::new("Other/Patches/copies.cfg")));
+        let database: Database = [part("a"), other, part("c")].into_iter().collect();
+
+        database.export(&root, &Writer::default())?;
+        let read = |path: &str| std::fs::read_to_string(root.join(path)).unwrap();
+        let (parts, copies) = (read("Mod/parts.cfg"), read("Other/Patches/copies.cfg"));
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(parts, "PART\n{\n\tname = a\n}\nPART\n{\n\tname = c\n}\n");
+        assert_eq!(copies, "PART\n{\n\tname = b\n}\n");
+
+        let mut outside = part("d");
+        outside.file_path = Some(Arc::from(Path::new("../escape.cfg")));
+        assert!(matches!(
+            Database::from_iter([outside]).export(&root, &Writer::default()),
+            Err(WriteError::InvalidPath(_))
+        ));
+        Ok(())
+    }
+}
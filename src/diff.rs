@@ -0,0 +1,340 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+use serde_json::json;
+
+use crate::config_node::{ConfigKey, ConfigNode, NodeList};
+use crate::database::Database;
+
+/// Structural difference between two [`Database`]s.
+///
+/// Top-level nodes are matched by their identifier, `name` key and `parentUrl`; nested nodes by
+/// identifier and `name`. Repeated keys and nodes sharing a match key are paired in order of
+/// appearance.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct DatabaseDiff<'a>(pub Vec<NodeDiff<'a>>);
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct NodeDiff<'a> {
+    pub ident: &'a str,
+    pub name: Option<&'a str>,
+    /// Only present for top-level nodes.
+    pub parent_url: Option<String>,
+    pub change: NodeChange<'a>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum NodeChange<'a> {
+    Added(&'a ConfigNode<'a>),
+    Removed(&'a ConfigNode<'a>),
+    Changed {
+        keys: Vec<KeyDiff<'a>>,
+        nodes: Vec<NodeDiff<'a>>,
+    },
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct KeyDiff<'a> {
+    pub ident: &'a str,
+    pub change: KeyChange<'a>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum KeyChange<'a> {
+    Added(&'a str),
+    Removed(&'a str),
+    Changed { old: &'a str, new: &'a str },
+}
+
+/// Normalize a node's file path into the form used by ModuleManager's `parentUrl`: relative,
+/// `/`-separated, and without the `.cfg` extension.
+pub fn parent_url(path: &Path) -> String {
+    let url = path.to_string_lossy().replace('\\', "/");
+    let url = url.trim_start_matches('/');
+    url.strip_suffix(".cfg").unwrap_or(url).to_owned()
+}
+
+impl<'a> DatabaseDiff<'a> {
+    pub fn new(old: &'a Database<'_>, new: &'a Database<'_>) -> Self {
+        let key = |node: &'a ConfigNode<'a>| {
+            (
+                &*node.ident,
+                node.name_key(),
+                node.file_path.as_deref().map(parent_url),
+            )
+        };
+        Self(diff_node_lists(old.nodes(), new.nodes(), key))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        self.0.iter().map(NodeDiff::to_json).collect()
+    }
+}
+
+fn diff_node_lists<'a, K>(
+    old: &'a NodeList<'a>,
+    new: &'a NodeList<'a>,
+    key: fn(&'a ConfigNode<'a>) -> K,
+) -> Vec<NodeDiff<'a>>
+where
+    K: Eq + std::hash::Hash,
+{
+    let new = new.iter().collect::<Vec<_>>();
+    let mut unmatched: HashMap<K, VecDeque<usize>> = HashMap::new();
+    for (idx, &node) in new.iter().enumerate() {
+        unmatched.entry(key(node)).or_default().push_back(idx);
+    }
+    let mut matched = vec![false; new.len()];
+
+    let mut diffs = vec![];
+    for old_node in old {
+        let counterpart = unmatched
+            .get_mut(&key(old_node))
+            .and_then(VecDeque::pop_front);
+        let change = match counterpart {
+            Some(idx) => {
+                matched[idx] = true;
+                let keys = diff_keys(&old_node.keys, &new[idx].keys);
+                let nodes = diff_node_lists(&old_node.nodes, &new[idx].nodes, |node| {
+                    (&*node.ident, node.name_key())
+                });
+                if keys.is_empty() && nodes.is_empty() {
+                    continue;
+                }
+                NodeChange::Changed { keys, nodes }
+            }
+            None => NodeChange::Removed(old_node),
+        };
+        diffs.push(NodeDiff::new(old_node, change));
+    }
+    for (node, _) in new.into_iter().zip(matched).filter(|(_, matched)| !matched) {
+        diffs.push(NodeDiff::new(node, NodeChange::Added(node)));
+    }
+    diffs
+}
+
+fn diff_keys<'a>(old: &'a [ConfigKey<'a>], new: &'a [ConfigKey<'a>]) -> Vec<KeyDiff<'a>> {
+    let mut unmatched: HashMap<&str, VecDeque<usize>> = HashMap::new();
+    for (idx, key) in new.iter().enumerate() {
+        unmatched.entry(&key.ident).or_default().push_back(idx);
+    }
+    let mut matched = vec![false; new.len()];
+
+    let mut diffs = vec![];
+    for old_key in old {
+        let counterpart = unmatched
+            .get_mut(&*old_key.ident)
+            .and_then(VecDeque::pop_front);
+        let change = match counterpart {
+            Some(idx) => {
+                matched[idx] = true;
+                if new[idx].value == old_key.value {
+                    continue;
+                }
+                KeyChange::Changed {
+                    old: &old_key.value,
+                    new: &new[idx].value,
+                }
+            }
+            None => KeyChange::Removed(&old_key.value),
+        };
+        diffs.push(KeyDiff {
+            ident: &old_key.ident,
+            change,
+        });
+    }
+    for (new_key, _) in new.iter().zip(matched).filter(|(_, matched)| !matched) {
+        diffs.push(KeyDiff {
+            ident: &new_key.ident,
+            change: KeyChange::Added(&new_key.value),
+        });
+    }
+    diffs
+}
+
+impl<'a> NodeDiff<'a> {
+    fn new(node: &'a ConfigNode<'a>, change: NodeChange<'a>) -> Self {
+        Self {
+            ident: &node.ident,
+            name: node.name_key(),
+            parent_url: node.file_path.as_deref().map(parent_url),
+            change,
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut value = json!({
+            "ident": self.ident,
+            "name": self.name,
+        });
+        if let Some(parent_url) = &self.parent_url {
+            value["parentUrl"] = json!(parent_url);
+        }
+        match &self.change {
+            NodeChange::Added(node) => {
+                value["change"] = json!("added");
+                value["node"] = node.to_json();
+            }
+            NodeChange::Removed(node) => {
+                value["change"] = json!("removed");
+                value["node"] = node.to_json();
+            }
+            NodeChange::Changed { keys, nodes } => {
+                value["change"] = json!("changed");
+                value["keys"] = keys.iter().map(KeyDiff::to_json).collect();
+                value["nodes"] = nodes.iter().map(NodeDiff::to_json).collect();
+            }
+        }
+        value
+    }
+
+    fn fmt_into(
+        &self,
+        f: &mut Formatter<'_>,
+        indent: usize,
+        indent_size: usize,
+    ) -> std::fmt::Result {
+        let marker = match self.change {
+            NodeChange::Added(_) => '+',
+            NodeChange::Removed(_) => '-',
+            NodeChange::Changed { .. } => '~',
+        };
+        write!(
+            f,
+            "{0:1$}{marker} {2}",
+            "",
+            indent_size * indent,
+            self.ident
+        )?;
+        if let Some(name) = self.name {
+            write!(f, "[{name}]")?;
+        }
+        if let Some(parent_url) = &self.parent_url {
+            write!(f, " ({parent_url})")?;
+        }
+        writeln!(f)?;
+        if let NodeChange::Changed { keys, nodes } = &self.change {
+            for key in keys {
+                key.fmt_into(f, indent + 1, indent_size)?;
+            }
+            for node in nodes {
+                node.fmt_into(f, indent + 1, indent_size)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> KeyDiff<'a> {
+    pub fn to_json(&self) -> serde_json::Value {
+        match self.change {
+            KeyChange::Added(value) => {
+                json!({ "change": "added", "ident": self.ident, "value": value })
+            }
+            KeyChange::Removed(value) => {
+                json!({ "change": "removed", "ident": self.ident, "value": value })
+            }
+            KeyChange::Changed { old, new } => {
+                json!({ "change": "changed", "ident": self.ident, "old": old, "new": new })
+            }
+        }
+    }
+
+    fn fmt_into(
+        &self,
+        f: &mut Formatter<'_>,
+        indent: usize,
+        indent_size: usize,
+    ) -> std::fmt::Result {
+        let pad = indent_size * indent;
+        let ident = self.ident;
+        match self.change {
+            KeyChange::Added(value) => writeln!(f, "{0:1$}+ {ident} = {value}", "", pad),
+            KeyChange::Removed(value) => writeln!(f, "{0:1$}- {ident} = {value}", "", pad),
+            KeyChange::Changed { old, new } => {
+                writeln!(f, "{0:1$}~ {ident} = {old} -> {new}", "", pad)
+            }
+        }
+    }
+}
+
+impl<'a> Display for DatabaseDiff<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for node in &self.0 {
+            node.fmt_into(f, 0, 4)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use super::*;
+
+    fn part<'a>(name: &'a str, keys: &[(&'a str, &'a str)]) -> ConfigNode<'a> {
+        let mut keys = keys
+            .iter()
+            .map(|&(ident, value)| ConfigKey::new(ident, value))
+            .collect::<Vec<_>>();
+        keys.insert(0, ConfigKey::new("name", name));
+        ConfigNode {
+            file_path: Some(Arc::from(Path::new("Mod/parts.cfg"))),
+            ident: "PART".into(),
+            nodes: NodeList::new(),
+            keys,
+            keys_before: 0,
+        }
+    }
+
+    #[test]
+    fn diff_databases() {
+        let old = [
+            part("a", &[("mass", "1")]),
+            part("b", &[]),
+            part("c", &[("cost", "5")]),
+        ]
+        .into_iter()
+        .collect::<Database>();
+        let new = [
+            part("c", &[("cost", "5")]),
+            part("a", &[("mass", "2"), ("tag", "x")]),
+            part("d", &[]),
+        ]
+        .into_iter()
+        .collect::<Database>();
+        let diff = DatabaseDiff::new(&old, &new);
+        let summary = diff
+            .0
+            .iter()
+            .map(|node| match &node.change {
+                NodeChange::Added(_) => format!("+{}", node.name.unwrap()),
+                NodeChange::Removed(_) => format!("-{}", node.name.unwrap()),
+                NodeChange::Changed { keys, .. } => {
+                    format!("~{}:{}", node.name.unwrap(), keys.len())
+                }
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(summary, ["~a:2", "-b", "+d"]);
+        assert!(DatabaseDiff::new(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn parent_url_normalization() {
+        assert_eq!(
+            parent_url(Path::new("Squad/Parts/part.cfg")),
+            "Squad/Parts/part"
+        );
+        assert_eq!(
+            parent_url(Path::new("/Squad/Parts/part")),
+            "Squad/Parts/part"
+        );
+    }
+}
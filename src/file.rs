@@ -1,15 +1,15 @@
 use std::path::Path;
-use std::rc::Rc;
+use std::sync::Arc;
 
 /// Otherwise known as `UrlConfig`.
 #[derive(Clone, Debug)]
 pub struct File<T> {
-    pub path: Rc<Path>,
+    pub path: Arc<Path>,
     pub contents: T,
 }
 
 impl<T> File<T> {
-    pub fn new(path: Rc<Path>, contents: T) -> Self {
+    pub fn new(path: Arc<Path>, contents: T) -> Self {
         Self { path, contents }
     }
 }
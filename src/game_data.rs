@@ -0,0 +1,339 @@
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use ksp_cfg_formatter::parser::Document;
+use walkdir::{DirEntry, WalkDir};
+
+use crate::database::Database;
+use crate::file::File;
+use crate::module_manager::ModuleManager;
+use crate::patch_set::PatchSet;
+use crate::raw_patch::RawPatches;
+use crate::PatchingError;
+
+/// Directories that are never searched for cfg files.
+const EXCLUDED_DIRECTORIES: &[&str] = &[
+    // KSP does not load anything from `PluginData`, where plugins keep their own files. This
+    // includes MM's own caches and logs.
+    "PluginData",
+    // MM's optional dump of the patched database.
+    "_MMCfgOutput",
+];
+
+#[derive(Debug, thiserror::Error)]
+pub enum LoadError {
+    #[error("failed to read the GameData directory: {0}")]
+    Walk(#[from] walkdir::Error),
+    #[error("failed to read `{path}`: {source}")]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("failed to parse `{path}`: {message}")]
+    Parse { path: PathBuf, message: String },
+    #[error(transparent)]
+    Patching(#[from] PatchingError),
+}
+
+/// A GameData directory, which owns the contents of its cfg files.
+///
+/// The parsed and patched forms borrow from this structure, so it only needs to be kept alive for
+/// as long as they are used. [`GameData::owned_database`] returns a database that does not borrow
+/// from it, for callers that keep the result around.
+#[derive(Clone, Debug, Default)]
+pub struct GameData {
+    files: Vec<File<String>>,
+    directories: Vec<PathBuf>,
+}
+
+impl GameData {
+    /// Read every cfg file under `root`. See [`discover`].
+    pub fn load(root: &Path) -> Result<Self, LoadError> {
+        let (directories, cfgs) = walk(root)?;
+        Ok(Self {
+            files: read(root, cfgs)?,
+            directories,
+        })
+    }
+
+    /// Construct a GameData from files that were read elsewhere. The paths of `files` must be
+    /// relative to the (virtual) GameData directory, which contains exactly the directories that
+    /// the files are in.
+    pub fn from_files(files: Vec<File<String>>) -> Self {
+        let directories = directories(files.iter().map(|file| &*file.path));
+        Self { files, directories }
+    }
+
+    pub fn files(&self) -> &[File<String>] {
+        &self.files
+    }
+
+    /// Every directory in GameData, relative to it.
+    pub fn directories(&self) -> impl Iterator<Item = &Path> {
+        self.directories.iter().map(AsRef::as_ref)
+    }
+
+    /// The names of the top-level directories, which ModuleManager adds to its list of mods.
+    pub fn mod_folders(&self) -> impl Iterator<Item = &str> {
+        self.directories
+            .iter()
+            .filter(|directory| directory.components().count() == 1)
+            .filter_map(|directory| directory.to_str())
+    }
+
+    /// Parse every file. With the `parallel` feature, files are parsed on rayon's thread pool; the
+    /// result is identical to parsing sequentially, and the reported error is that of the first
+    /// file (in load order) that fails to parse.
+    pub fn parse(&self) -> Result<RawPatches<'_>, LoadError> {
+        #[cfg(feature = "parallel")]
+        let documents: Vec<_> = {
+            use rayon::prelude::*;
+            self.files.par_iter().map(parse_file).collect()
+        };
+        #[cfg(not(feature = "parallel"))]
+        let documents: Vec<_> = self.files.iter().map(parse_file).collect();
+
+        Ok(RawPatches {
+            files: documents.into_iter().collect::<Result<_, _>>()?,
+        })
+    }
+
+    pub fn patch_set(&self) -> Result<PatchSet<'_>, LoadError> {
+        Ok(self.parse()?.extract()?)
+    }
+
+    /// Prepare to patch this GameData. Besides the top-level directories of GameData and mods that
+    /// declare a `:FOR` pass, `dll_names` are registered as installed mods.
+    pub fn module_manager<'a>(
+        &'a self,
+        dll_names: impl Iterator<Item = &'a str>,
+    ) -> Result<ModuleManager<'a>, LoadError> {
+        Ok(ModuleManager::new(
+            self.parse()?,
+            dll_names,
+            self.directories(),
+        )?)
+    }
+
+    /// Patch this GameData, returning the final database.
+    pub fn database<'a>(
+        &'a self,
+        dll_names: impl Iterator<Item = &'a str>,
+    ) -> Result<Database<'a>, LoadError> {
+        Ok(self.module_manager(dll_names)?.execute()?)
+    }
+
+    /// Patch this GameData, returning a final database that owns its contents.
+    pub fn owned_database<'a>(
+        &'a self,
+        dll_names: impl Iterator<Item = &'a str>,
+    ) -> Result<Database<'static>, LoadError> {
+        Ok(self.database(dll_names)?.into_owned())
+    }
+}
+
+fn parse_file(cfg: &File<String>) -> Result<File<Document<'_>>, LoadError> {
+    log::info!("parsing {:?}", cfg.path);
+    let document =
+        ksp_cfg_formatter::parse_to_ast(&cfg.contents).map_err(|e| LoadError::Parse {
+            path: cfg.path.to_path_buf(),
+            message: e.to_string(),
+        })?;
+    Ok(File::new(Arc::clone(&cfg.path), document))
+}
+
+/// Find every cfg file under `game_data`, in a deterministic order. Symbolic links are followed;
+/// links that would create a cycle are skipped.
+///
+/// Paths are relative to `game_data`, matching ModuleManager's `parentUrl`.
+pub fn discover(game_data: &Path) -> Result<Vec<PathBuf>, LoadError> {
+    Ok(walk(game_data)?.1)
+}
+
+/// Find every directory and cfg file under `game_data`, relative to it.
+pub(crate) fn walk(game_data: &Path) -> Result<(Vec<PathBuf>, Vec<PathBuf>), LoadError> {
+    let mut directories = vec![];
+    let mut cfgs = vec![];
+    let walker = WalkDir::new(game_data)
+        .follow_links(true)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| !is_excluded_directory(entry));
+    for entry in walker {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) if e.loop_ancestor().is_some() => {
+                log::warn!("skipping symbolic link cycle: {e}");
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let relative = entry
+            .path()
+            .strip_prefix(game_data)
+            .expect("walked paths are prefixed by the root");
+        if entry.file_type().is_dir() && entry.depth() > 0 {
+            directories.push(relative.to_owned());
+        } else if entry.file_type().is_file() && is_cfg(entry.path()) {
+            cfgs.push(relative.to_owned());
+        }
+    }
+    Ok((directories, cfgs))
+}
+
+/// Read every cfg file under `game_data`. See [`discover`].
+pub fn load(game_data: &Path) -> Result<Vec<File<String>>, LoadError> {
+    read(game_data, discover(game_data)?)
+}
+
+/// The directories containing the given files, and all of their ancestors, in sorted order.
+pub fn directories<'p>(files: impl Iterator<Item = &'p Path>) -> Vec<PathBuf> {
+    let mut directories = files
+        .filter_map(Path::parent)
+        .flat_map(Path::ancestors)
+        .filter(|ancestor| !ancestor.as_os_str().is_empty())
+        .map(Path::to_path_buf)
+        .collect::<Vec<_>>();
+    directories.sort();
+    directories.dedup();
+    directories
+}
+
+fn read(game_data: &Path, cfgs: Vec<PathBuf>) -> Result<Vec<File<String>>, LoadError> {
+    cfgs.into_iter()
+        .map(|path| {
+            let contents = std::fs::read_to_string(game_data.join(&path)).map_err(|source| {
+                LoadError::Read {
+                    path: game_data.join(&path),
+                    source,
+                }
+            })?;
+            Ok(File::new(Arc::from(path), contents))
+        })
+        .collect()
+}
+
+fn is_excluded_directory(entry: &DirEntry) -> bool {
+    entry.depth() > 0
+        && entry.file_type().is_dir()
+        && EXCLUDED_DIRECTORIES.iter().any(|excluded| {
+            entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| name.eq_ignore_ascii_case(excluded))
+        })
+}
+
+fn is_cfg(path: &Path) -> bool {
+    path.extension()
+        .and_then(OsStr::to_str)
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("cfg"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn owned_database_outlives_game_data() {
+        let database = {
+            let game_data = GameData::from_files(vec![File::new(
+                Arc::from(Path::new("Mod/parts.cfg")),
+                "PART\n{\n\tname = a\n}\n@PART[a]\n{\n\tmass = 1\n}\n".to_owned(),
+            )]);
+            game_data.owned_database(std::iter::empty()).unwrap()
+        };
+        let part = database.nodes().iter().next().unwrap();
+        assert_eq!(part.name_key(), Some("a"));
+        assert_eq!(part.keys.len(), 2);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_parse_matches_sequential() {
+        let files = |broken: &[usize]| {
+            let files = (0..200)
+                .map(|i| {
+                    let contents = if broken.contains(&i) {
+                        format!("PART[p{i}]\n")
+                    } else {
+                        format!(
+                            "PART\n{{\n\tname = p{i}\n}}\n@PART[p{}]:FOR[Mod{}]\n{{\n\tx = {i}\n}}\n",
+                            (i * 7) % 200,
+                            i % 5
+                        )
+                    };
+                    File::new(Arc::from(PathBuf::from(format!("Mod{}/{i}.cfg", i % 5))), contents)
+                })
+                .collect();
+            GameData::from_files(files)
+        };
+
+        let game_data = files(&[]);
+        let parallel = game_data.parse().unwrap();
+        let sequential = game_data
+            .files()
+            .iter()
+            .map(parse_file)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            format!("{:?}", parallel.files),
+            format!("{sequential:?}"),
+            "files differ in order or contents"
+        );
+        let sequential = ModuleManager::new(
+            RawPatches { files: sequential },
+            std::iter::empty(),
+            game_data.directories(),
+        )
+        .unwrap()
+        .execute()
+        .unwrap();
+        assert_eq!(game_data.database(std::iter::empty()).unwrap(), sequential);
+
+        // The first broken file in load order is reported, however the work is scheduled.
+        let broken = files(&[150, 20, 90]);
+        match broken.parse() {
+            Err(LoadError::Parse { path, .. }) => assert_eq!(path, Path::new("Mod0/20.cfg")),
+            other => panic!("expected a parse error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn discover_skips_excluded() -> std::io::Result<()> {
+        let root = std::env::temp_dir().join(format!("mm_rs_discover_{}", std::process::id()));
+        for dir in [
+            "Mod/Parts",
+            "Mod/PluginData",
+            "Mod/pluginData/Nested",
+            "_MMCfgOutput",
+        ] {
+            std::fs::create_dir_all(root.join(dir))?;
+        }
+        for file in [
+            "Mod/b.cfg",
+            "Mod/Parts/a.CFG",
+            "Mod/Parts/readme.txt",
+            "Mod/PluginData/settings.cfg",
+            "Mod/pluginData/Nested/settings.cfg",
+            "_MMCfgOutput/dump.cfg",
+            "ModuleManager.ConfigCache",
+        ] {
+            std::fs::write(root.join(file), "")?;
+        }
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&root, root.join("Mod/Parts/loop"))?;
+
+        let discovered = super::discover(&root);
+        std::fs::remove_dir_all(&root)?;
+        assert_eq!(
+            discovered.unwrap(),
+            [PathBuf::from("Mod/Parts/a.CFG"), PathBuf::from("Mod/b.cfg")]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,431 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use ksp_cfg_formatter::parser::Document;
+use self_cell::self_cell;
+
+use crate::database::Database;
+use crate::file::File;
+use crate::game_data::{self, LoadError};
+use crate::module_manager::operator::needs::Installed;
+use crate::module_manager::ModuleManager;
+use crate::pass::Pass;
+use crate::raw_patch::RawPatches;
+
+/// A pass, and the files that have patches in it.
+type PassFiles = (Pass<'static>, HashSet<Arc<Path>>);
+
+/// The number of snapshots kept at passes spread evenly over a run.
+const CHECKPOINTS: usize = 8;
+/// The number of snapshots kept at the passes that were most recently affected by a change, since
+/// a file that was just saved is likely to be saved again.
+const RECENT: usize = 4;
+
+self_cell!(
+    /// The contents of a file, and the document parsed from them.
+    struct ParsedFile {
+        owner: String,
+        #[covariant]
+        dependent: Document,
+    }
+);
+
+/// The outcome of [`Incremental::update`].
+#[derive(Debug, Default)]
+#[must_use]
+pub struct Update {
+    /// The pass that execution resumed from, or `None` if no patches were affected or patching
+    /// failed.
+    pub resumed_from: Option<Pass<'static>>,
+    /// Errors reading or parsing files, whose previous versions were kept, and the error of
+    /// patching, if any.
+    pub errors: Vec<LoadError>,
+}
+
+/// A GameData directory that is patched again whenever its files change. Only changed files are
+/// parsed again, and execution resumes from the latest snapshot of the database taken at or
+/// before the earliest pass with patches from a changed file.
+///
+/// Snapshots are owned copies of the database, so only a bounded number of them is kept: at
+/// [`CHECKPOINTS`] passes spread over the run, and at the [`RECENT`] passes that were most
+/// recently affected by a change.
+pub struct Incremental {
+    root: PathBuf,
+    dll_names: Vec<String>,
+    files: BTreeMap<Arc<Path>, ParsedFile>,
+    directories: Vec<PathBuf>,
+    /// The passes of the last run, or none if it failed.
+    passes: Vec<PassFiles>,
+    /// The database before some of the passes of the last run, by the index of the pass.
+    snapshots: BTreeMap<usize, Database<'static>>,
+    /// The indices of the passes that were most recently affected by a change, latest last.
+    recent: VecDeque<usize>,
+    /// The database as of the last successful run.
+    database: Option<Database<'static>>,
+}
+
+impl Incremental {
+    /// Load and patch every cfg file under `root`. `dll_names` are registered as installed mods,
+    /// as in [`GameData::module_manager`](crate::game_data::GameData::module_manager).
+    pub fn load(root: &Path, dll_names: &[String]) -> Result<Self, LoadError> {
+        let mut incremental = Self {
+            root: root.to_path_buf(),
+            dll_names: dll_names.to_vec(),
+            files: BTreeMap::new(),
+            directories: vec![],
+            passes: vec![],
+            snapshots: BTreeMap::new(),
+            recent: VecDeque::new(),
+            database: None,
+        };
+        match incremental
+            .update(std::iter::empty())
+            .errors
+            .into_iter()
+            .next()
+        {
+            Some(e) => Err(e),
+            None => Ok(incremental),
+        }
+    }
+
+    /// The database as of the last successful run.
+    pub fn database(&self) -> Option<&Database<'static>> {
+        self.database.as_ref()
+    }
+
+    /// The mod list, without the mods that are only declared by a `:FOR` pass, as expected by
+    /// [`RawPatches::lint`].
+    pub fn installed(&self) -> Installed<'_> {
+        Installed::new(
+            self.dll_names.iter().map(String::as_str),
+            self.directories.iter().map(PathBuf::as_path),
+        )
+    }
+
+    /// The parsed files, by their path relative to GameData.
+    pub fn documents(&self) -> impl Iterator<Item = (&Arc<Path>, &Document<'_>)> {
+        self.files
+            .iter()
+            .map(|(path, file)| (path, file.borrow_dependent()))
+    }
+
+    /// The passes of the last run, in execution order.
+    pub fn passes(&self) -> impl Iterator<Item = &Pass<'static>> {
+        self.passes.iter().map(|(pass, _)| pass)
+    }
+
+    /// Read the files at `changed`, either absolute or relative to GameData, as well as files that
+    /// were added or removed, and patch again.
+    ///
+    /// A file that fails to be read or parsed does not stop the others from being updated: the
+    /// previous version of it is kept, so that it is read again once it changes, and the error is
+    /// returned along with the outcome of patching the rest.
+    pub fn update(&mut self, changed: impl IntoIterator<Item = PathBuf>) -> Update {
+        let mut update = Update::default();
+        let (directories, cfgs) = match game_data::walk(&self.root) {
+            Ok(walked) => walked,
+            Err(e) => {
+                update.errors.push(e);
+                return update;
+            }
+        };
+        let cfgs = cfgs
+            .into_iter()
+            .map(Arc::from)
+            .collect::<HashSet<Arc<Path>>>();
+        let mut changed = changed
+            .into_iter()
+            .map(|path| match path.strip_prefix(&self.root) {
+                Ok(relative) => Arc::from(relative),
+                Err(_) => Arc::from(path),
+            })
+            .filter(|path| cfgs.contains(path))
+            .collect::<HashSet<_>>();
+        changed.extend(
+            cfgs.iter()
+                .filter(|path| !self.files.contains_key(*path))
+                .cloned(),
+        );
+
+        changed.retain(|path| match self.read(path) {
+            Ok(file) => {
+                self.files.insert(path.clone(), file);
+                true
+            }
+            Err(e) => {
+                update.errors.push(e);
+                false
+            }
+        });
+        let removed = self
+            .files
+            .keys()
+            .filter(|path| !cfgs.contains(*path))
+            .cloned()
+            .collect::<Vec<_>>();
+        for path in removed {
+            self.files.remove(&path);
+            changed.insert(path);
+        }
+
+        let directories_changed = directories != self.directories;
+        if directories_changed {
+            self.directories = directories;
+        }
+        if changed.is_empty() && !directories_changed {
+            return update;
+        }
+        match self.run(&changed, directories_changed) {
+            Ok(resumed_from) => update.resumed_from = resumed_from,
+            Err(e) => update.errors.push(e),
+        }
+        update
+    }
+
+    fn read(&self, path: &Path) -> Result<ParsedFile, LoadError> {
+        let full_path = self.root.join(path);
+        let contents = std::fs::read_to_string(&full_path).map_err(|source| LoadError::Read {
+            path: full_path,
+            source,
+        })?;
+        log::info!("parsing {path:?}");
+        ParsedFile::try_new(contents, |contents| {
+            ksp_cfg_formatter::parse_to_ast(contents).map_err(|e| LoadError::Parse {
+                path: path.to_path_buf(),
+                message: e.to_string(),
+            })
+        })
+    }
+
+    fn run(
+        &mut self,
+        changed: &HashSet<Arc<Path>>,
+        full: bool,
+    ) -> Result<Option<Pass<'static>>, LoadError> {
+        let raw = RawPatches {
+            files: self
+                .files
+                .iter()
+                .map(|(path, file)| File::new(path.clone(), file.borrow_dependent().clone()))
+                .collect(),
+        };
+        let mm = ModuleManager::new(
+            raw,
+            self.dll_names.iter().map(String::as_str),
+            self.directories.iter().map(PathBuf::as_path),
+        )?;
+        let passes = mm
+            .patch_set()
+            .iter()
+            .map(|(pass, files)| {
+                let paths = files
+                    .iter()
+                    .filter(|file| !file.contents.is_empty())
+                    .map(|file| file.path.clone())
+                    .collect::<HashSet<_>>();
+                (pass.clone().into_owned(), paths)
+            })
+            .collect::<Vec<PassFiles>>();
+
+        // The snapshots can only be reused if the passes before the earliest affected one are
+        // the same. Changes to the mod list, e.g. a new `:FOR`, add or remove passes.
+        let same_passes = self.passes.len() == passes.len()
+            && self.passes.iter().zip(&passes).all(|(a, b)| a.0 == b.0);
+        if full || !same_passes {
+            self.snapshots.clear();
+            self.recent.clear();
+        }
+        let affected = |(old, new): (&PassFiles, &PassFiles)| {
+            !old.1.is_disjoint(changed) || !new.1.is_disjoint(changed)
+        };
+        let first = if full || !same_passes {
+            0
+        } else {
+            match self.passes.iter().zip(&passes).position(affected) {
+                Some(first) => first,
+                None => return Ok(None),
+            }
+        };
+        if passes.is_empty() {
+            return Ok(None);
+        }
+
+        // Snapshots after the first affected pass are out of date.
+        self.snapshots.split_off(&(first + 1));
+        self.recent.retain(|&pass| pass != first);
+        self.recent.push_back(first);
+        if self.recent.len() > RECENT {
+            self.recent.pop_front();
+        }
+        let keep = (0..passes.len())
+            .step_by(passes.len().div_ceil(CHECKPOINTS))
+            .chain(self.recent.iter().copied())
+            .collect::<BTreeSet<_>>();
+
+        let (start, database) = match self.snapshots.range(..=first).next_back() {
+            Some((&start, database)) => (start, database.clone()),
+            None => (0, Database::default()),
+        };
+        let start_pass = passes[start].0.clone();
+        log::info!("executing from pass {start_pass}");
+
+        let mut index = start;
+        let snapshots = &mut self.snapshots;
+        let result = mm.execute_from(database, &start_pass, |_, database| {
+            // The database before the next pass.
+            index += 1;
+            if index < passes.len() && keep.contains(&index) {
+                snapshots
+                    .entry(index)
+                    .or_insert_with(|| database.clone().into_owned());
+            }
+            ControlFlow::Continue(())
+        });
+        match result {
+            Ok(database) => {
+                self.database = Some(database.into_owned());
+                self.passes = passes;
+                self.snapshots.retain(|index, _| keep.contains(index));
+                Ok(Some(start_pass))
+            }
+            Err(e) => {
+                // Run every pass the next time.
+                self.passes.clear();
+                self.snapshots.clear();
+                Err(e.into())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game_data::GameData;
+
+    /// A directory under the system's temporary directory, removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            Self(std::env::temp_dir().join(format!("mm_rs_{name}_{}", std::process::id())))
+        }
+
+        fn write(&self, path: &str, contents: &str) {
+            let path = self.0.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Update `incremental` after `changed` was written, and check that the result matches
+    /// patching everything from scratch.
+    fn update(incremental: &mut Incremental, changed: &[&str]) -> Option<Pass<'static>> {
+        let root = incremental.root.clone();
+        let update = incremental.update(changed.iter().map(|path| root.join(path)));
+        assert!(update.errors.is_empty(), "{:?}", update.errors);
+        let game_data = GameData::load(&root).unwrap();
+        let expected = game_data.database(std::iter::empty()).unwrap();
+        assert_eq!(incremental.database(), Some(&expected));
+        update.resumed_from
+    }
+
+    #[test]
+    fn resumes_from_affected_pass() {
+        let root = TempDir::new("incremental");
+        root.write("ModA/parts.cfg", "PART\n{\n\tname = a\n}\n");
+        root.write("ModA/patch.cfg", "@PART[a]:FOR[ModA]\n{\n\tmass = 1\n}\n");
+        root.write("ModB/patch.cfg", "@PART[a]:FOR[ModB]\n{\n\tcost = 1\n}\n");
+
+        let mut incremental = Incremental::load(&root.0, &[]).unwrap();
+        assert_eq!(update(&mut incremental, &[]), None);
+
+        root.write("ModB/patch.cfg", "@PART[a]:FOR[ModB]\n{\n\tcost = 2\n}\n");
+        let first = update(&mut incremental, &["ModB/patch.cfg"]);
+        assert_eq!(first, Some(pass![FOR["ModB"]]));
+
+        // A change in a pass between others reuses the snapshot before it, and runs the later
+        // passes again.
+        root.write("ModA/patch.cfg", "@PART[a]:FOR[ModA]\n{\n\tmass = 2\n}\n");
+        let first = update(&mut incremental, &["ModA/patch.cfg"]);
+        assert_eq!(first, Some(pass![FOR["ModA"]]));
+
+        // Adding a file in a new mod adds passes, so everything runs again.
+        root.write("ModC/patch.cfg", "@PART[a]:FOR[ModC]\n{\n\ttag = c\n}\n");
+        assert_eq!(update(&mut incremental, &[]), Some(Pass::Insert));
+    }
+
+    #[test]
+    fn keeps_a_bounded_number_of_snapshots() {
+        let root = TempDir::new("incremental_snapshots");
+        root.write("Mod00/parts.cfg", "PART\n{\n\tname = a\n}\n");
+        let write = |idx: usize, mass: usize| {
+            root.write(
+                &format!("Mod{idx:02}/patch.cfg"),
+                &format!("@PART[a]:FOR[Mod{idx:02}]\n{{\n\tmass = {mass}\n}}\n"),
+            );
+        };
+        for idx in 0..20 {
+            write(idx, idx);
+        }
+        let mut incremental = Incremental::load(&root.0, &[]).unwrap();
+        assert!(incremental.passes.len() > 2 * CHECKPOINTS);
+        assert!(incremental.snapshots.len() <= CHECKPOINTS);
+
+        for (idx, mass) in [(13, 100), (7, 101), (13, 102)] {
+            write(idx, mass);
+            let name = format!("Mod{idx:02}");
+            let resumed = update(&mut incremental, &[&format!("{name}/patch.cfg")]).unwrap();
+            assert!(resumed <= Pass::For(name.as_str().into()));
+            assert!(incremental.snapshots.len() <= CHECKPOINTS + RECENT);
+        }
+        // A file that was changed recently resumes from its own pass.
+        write(7, 103);
+        assert_eq!(
+            update(&mut incremental, &["Mod07/patch.cfg"]),
+            Some(pass![FOR["Mod07"]])
+        );
+    }
+
+    #[test]
+    fn keeps_files_that_parse() {
+        let root = TempDir::new("incremental_errors");
+        root.write("ModA/parts.cfg", "PART\n{\n\tname = a\n}\n");
+        root.write("ModA/patch.cfg", "@PART[a]:FOR[ModA]\n{\n\tmass = 1\n}\n");
+        root.write("ModB/patch.cfg", "@PART[a]:FOR[ModB]\n{\n\tcost = 1\n}\n");
+        let mut incremental = Incremental::load(&root.0, &[]).unwrap();
+
+        root.write("ModA/patch.cfg", "@PART[a]:FOR[ModA]\n{\n\tmass = 2\n}\n");
+        root.write("ModB/patch.cfg", "@PART[a]:FOR[ModB]\n{\n");
+        let update =
+            incremental.update([root.0.join("ModA/patch.cfg"), root.0.join("ModB/patch.cfg")]);
+        assert!(matches!(
+            &update.errors[..],
+            [LoadError::Parse { path, .. }] if path == Path::new("ModB/patch.cfg")
+        ));
+        assert_eq!(update.resumed_from, Some(pass![FOR["ModA"]]));
+        let part = incremental
+            .database()
+            .unwrap()
+            .nodes()
+            .iter()
+            .next()
+            .unwrap();
+        let keys = part
+            .keys
+            .iter()
+            .map(|key| (&*key.ident, &*key.value))
+            .collect::<Vec<_>>();
+        // The previous version of the broken file still applies.
+        assert_eq!(keys, [("name", "a"), ("mass", "2"), ("cost", "1")]);
+    }
+}
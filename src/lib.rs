@@ -11,13 +11,20 @@ macro_rules! rt_error {
 pub mod pass;
 pub mod config_node;
 pub mod database;
+pub mod diff;
 pub mod file;
+pub mod game_data;
+pub mod incremental;
 pub mod key_patch;
+pub mod lint;
+pub mod location;
 pub mod module_manager;
 pub mod node_patch;
 pub mod operation;
 pub mod patch_set;
 pub mod raw_patch;
+pub mod validate;
+pub mod writer;
 
 use std::borrow::Cow;
 use std::path::Path;
@@ -29,6 +36,8 @@ pub enum PatchingError {
     Internal(Cow<'static, str>),
     #[error("error when evaluating `{path}`: {kind}")]
     Runtime { path: Arc<Path>, kind: RuntimeError },
+    #[error("malformed ConfigCache: {0}")]
+    MalformedCache(Cow<'static, str>),
 }
 
 #[derive(Clone, PartialEq, Debug)]
@@ -59,3 +68,14 @@ pub(crate) fn internal_error<T>(msg: impl Into<Cow<'static, str>>) -> Result<T>
 }
 
 pub type Result<T = ()> = std::result::Result<T, PatchingError>;
+
+/// The patching core is shared with worker threads, so make sure it stays `Send + Sync`.
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<database::Database<'static>>();
+    assert_send_sync::<patch_set::PatchSet<'static>>();
+    assert_send_sync::<raw_patch::WorkingPatchSet<'static>>();
+    assert_send_sync::<module_manager::ModuleManager<'static>>();
+    assert_send_sync::<game_data::GameData>();
+    assert_send_sync::<PatchingError>();
+};
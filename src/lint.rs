@@ -0,0 +1,365 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use std::sync::Arc;
+
+use ksp_cfg_formatter::parser::{self, NodeItem};
+
+use crate::location::Location;
+use crate::module_manager::operator::needs::Installed;
+use crate::operation::Op;
+use crate::pass::PassIdentifier;
+use crate::raw_patch::RawPatches;
+use crate::validate::{self, Severity};
+
+/// A likely mistake in a patch, found without executing it.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Lint<'a> {
+    pub rule: Rule,
+    pub severity: Severity,
+    pub path: Arc<Path>,
+    /// The text that the lint points at. It borrows from the contents of the file, which locates
+    /// the lint within it; see [`Lint::location`].
+    pub anchor: &'a str,
+    pub message: String,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Rule {
+    /// An edit that sets a key to nothing, e.g. `@mass =`. An edit without any `=`, e.g. `@mass`,
+    /// is not a key at all: the parser reads it as the header of a node, and rejects it when no
+    /// block follows.
+    EmptyEdit,
+    /// A `:HAS` on a nested insertion node, which MM ignores.
+    HasOnInsert,
+    /// A `:FOR` pass named after a mod that is installed in another folder, which makes the patch
+    /// run as part of that mod.
+    ForClaimsMod,
+    /// A `:NEEDS` on a mod or directory that is not installed, which may be misspelled.
+    UnknownNeeds,
+    /// An index on an insertion node or key, which MM ignores.
+    IndexOnInsert,
+    /// The rename operator `|` on a node, which fails when executed.
+    RenameNode,
+    /// A top-level insertion node with the same type and name as an earlier one.
+    DuplicateNode,
+}
+
+impl Rule {
+    /// The identifier of the rule, e.g. `empty-edit`.
+    pub fn id(self) -> &'static str {
+        match self {
+            Self::EmptyEdit => "empty-edit",
+            Self::HasOnInsert => "has-on-insert",
+            Self::ForClaimsMod => "for-claims-mod",
+            Self::UnknownNeeds => "unknown-needs",
+            Self::IndexOnInsert => "index-on-insert",
+            Self::RenameNode => "rename-node",
+            Self::DuplicateNode => "duplicate-node",
+        }
+    }
+}
+
+impl<'a> Lint<'a> {
+    /// The position of the lint within `source`, the contents of its file, or `None` if the
+    /// anchor does not point into `source`.
+    pub fn location(&self, source: &str) -> Option<Location> {
+        Location::of(source, self.anchor)
+    }
+}
+
+impl<'a> RawPatches<'a> {
+    /// Check every patch for likely mistakes. `installed` is the mod list without the mods that
+    /// are only declared by a `:FOR` pass, which are taken from the patches themselves.
+    pub fn lint(&self, installed: &Installed) -> Vec<Lint<'a>> {
+        self.lint_part(installed, [])
+    }
+
+    /// Like [`lint`](Self::lint), for patches that are only part of the installed ones, e.g. a
+    /// single file being edited. `declared` are the mods declared by a `:FOR` pass in the rest.
+    /// Duplicate nodes are only found within the part.
+    pub fn lint_part(
+        &self,
+        installed: &Installed,
+        declared: impl IntoIterator<Item = PassIdentifier<'a>>,
+    ) -> Vec<Lint<'a>> {
+        let mut linter = Linter {
+            installed,
+            declared: declared.into_iter().collect(),
+            lints: vec![],
+        };
+        for file in &self.files {
+            for node in top_level_nodes(&file.contents.statements) {
+                if let parser::Pass::For(name) = node.pass {
+                    linter.declared.insert(PassIdentifier::from(name));
+                }
+            }
+        }
+
+        let mut inserted = HashMap::new();
+        for file in &self.files {
+            for node in top_level_nodes(&file.contents.statements) {
+                linter.lint_top_level(&file.path, node, &mut inserted);
+            }
+        }
+        linter.lints
+    }
+}
+
+struct Linter<'b, 'a> {
+    installed: &'b Installed<'b>,
+    declared: HashSet<PassIdentifier<'a>>,
+    lints: Vec<Lint<'a>>,
+}
+
+impl<'b, 'a> Linter<'b, 'a> {
+    fn report(
+        &mut self,
+        rule: Rule,
+        severity: Severity,
+        path: &Arc<Path>,
+        anchor: &'a str,
+        message: String,
+    ) {
+        self.lints.push(Lint {
+            rule,
+            severity,
+            path: path.clone(),
+            anchor,
+            message,
+        });
+    }
+
+    fn lint_top_level(
+        &mut self,
+        path: &Arc<Path>,
+        node: &parser::Node<'a>,
+        inserted: &mut HashMap<(&'a str, &'a str), Arc<Path>>,
+    ) {
+        if let parser::Pass::For(name) = node.pass {
+            let mod_folder = path
+                .components()
+                .next()
+                .map(|component| component.as_os_str().to_string_lossy());
+            let ident = PassIdentifier::from(name);
+            if self.installed.mods.contains(&ident) && mod_folder.map(PassIdentifier) != Some(ident)
+            {
+                self.report(
+                    Rule::ForClaimsMod,
+                    Severity::Warning,
+                    path,
+                    name,
+                    format!("`:FOR[{name}]` runs this patch as part of the installed mod `{name}`"),
+                );
+            }
+        }
+
+        let name = node.block.iter().find_map(|item| match item {
+            NodeItem::KeyVal(key) if key.key == "name" => Some(key.val),
+            _ => None,
+        });
+        if let (Op::Insert, parser::Pass::Default, Some(name)) =
+            (validate::operation(node), node.pass, name)
+        {
+            match inserted.get(&(node.identifier, name)) {
+                Some(first) => self.report(
+                    Rule::DuplicateNode,
+                    Severity::Warning,
+                    path,
+                    node.identifier,
+                    format!(
+                        "`{}[{name}]` was already inserted in {}",
+                        node.identifier,
+                        first.to_string_lossy()
+                    ),
+                ),
+                None => {
+                    inserted.insert((node.identifier, name), path.clone());
+                }
+            }
+        }
+
+        self.lint_node(path, node, true);
+    }
+
+    fn lint_node(&mut self, path: &Arc<Path>, node: &parser::Node<'a>, is_top_level: bool) {
+        let operation = validate::operation(node);
+        if operation == Op::Insert && node.has.is_some() && !is_top_level {
+            self.report(
+                Rule::HasOnInsert,
+                Severity::Warning,
+                path,
+                node.identifier,
+                format!(
+                    "`:HAS` on the insertion node `{}` is ignored",
+                    validate::header(node)
+                ),
+            );
+        }
+        if operation == Op::Insert && node.index.is_some() {
+            self.report(
+                Rule::IndexOnInsert,
+                Severity::Warning,
+                path,
+                node.identifier,
+                format!(
+                    "the index of the insertion node `{}` is ignored",
+                    node.identifier
+                ),
+            );
+        }
+        if operation == Op::Rename {
+            self.report(
+                Rule::RenameNode,
+                Severity::Error,
+                path,
+                node.identifier,
+                crate::RuntimeError::CannotRenameNode.to_string(),
+            );
+        }
+        if let Some(needs) = &node.needs {
+            self.lint_needs(path, needs);
+        }
+
+        for item in &node.block {
+            match item {
+                NodeItem::Node(child) => self.lint_node(path, child, false),
+                NodeItem::KeyVal(key) => self.lint_key(path, key),
+                NodeItem::Comment(_) | NodeItem::EmptyLine => {}
+            }
+        }
+    }
+
+    fn lint_key(&mut self, path: &Arc<Path>, key: &parser::KeyVal<'a>) {
+        let operation = Op::new(key.operator.clone(), None);
+        if operation == Op::Edit && key.val.is_empty() {
+            self.report(
+                Rule::EmptyEdit,
+                Severity::Warning,
+                path,
+                key.key,
+                format!("`@{}` sets the key to an empty value", key.key),
+            );
+        }
+        if operation == Op::Insert && key.index.is_some() {
+            self.report(
+                Rule::IndexOnInsert,
+                Severity::Warning,
+                path,
+                key.key,
+                format!("the index of the inserted key `{}` is ignored", key.key),
+            );
+        }
+        if let Some(needs) = &key.needs {
+            self.lint_needs(path, needs);
+        }
+    }
+
+    fn lint_needs(&mut self, path: &Arc<Path>, needs: &parser::NeedsBlock<'a>) {
+        for need in needs.or_clauses.iter().flat_map(|or| &or.mod_clauses) {
+            let exists = if need.name.contains('/') {
+                let directory = need.name.trim_matches('/');
+                self.installed
+                    .directories
+                    .contains(&PassIdentifier::from(directory))
+            } else {
+                let ident = PassIdentifier::from(need.name);
+                self.installed.mods.contains(&ident) || self.declared.contains(&ident)
+            };
+            if !exists {
+                self.report(
+                    Rule::UnknownNeeds,
+                    Severity::Warning,
+                    path,
+                    need.name,
+                    format!("`:NEEDS` on `{}`, which is not installed", need.name),
+                );
+            }
+        }
+    }
+}
+
+fn top_level_nodes<'b, 'a>(
+    statements: &'b [NodeItem<'a>],
+) -> impl Iterator<Item = &'b parser::Node<'a>> {
+    statements.iter().filter_map(|item| match item {
+        NodeItem::Node(node) => Some(node),
+        _ => None,
+    })
+}
+
+impl<'a> Display for Lint<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{severity}[{}]: {}", self.rule.id(), self.message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::File;
+    use crate::game_data::GameData;
+
+    #[test]
+    fn lints_with_locations() {
+        let patches = "\
+PART
+{
+\tname = a
+}
+@PART[a]:FOR[ModB]:NEEDS[ModC]
+{
+\t@mass =
+\tMODULE:HAS[#name]
+\t{
+\t}
+\t|RESOURCE
+\t{
+\t}
+}
+";
+        let game_data = GameData::from_files(vec![
+            File::new(Arc::from(Path::new("ModA/a.cfg")), patches.to_owned()),
+            File::new(
+                Arc::from(Path::new("ModB/b.cfg")),
+                "PART\n{\n\tname = a\n}\nPART:HAS[#mass]\n{\n}\n".into(),
+            ),
+        ]);
+        let raw = game_data.parse().unwrap();
+        let installed = Installed::new(std::iter::empty(), game_data.directories());
+        let lints = raw
+            .lint(&installed)
+            .into_iter()
+            .map(|lint| {
+                let source = &game_data
+                    .files()
+                    .iter()
+                    .find(|file| file.path == lint.path)
+                    .unwrap()
+                    .contents;
+                let location = lint.location(source).unwrap();
+                (lint.rule, location.line, location.column)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lints,
+            [
+                (Rule::ForClaimsMod, 5, 14),
+                (Rule::UnknownNeeds, 5, 26),
+                (Rule::EmptyEdit, 7, 3),
+                (Rule::HasOnInsert, 8, 2),
+                (Rule::RenameNode, 11, 3),
+                (Rule::DuplicateNode, 1, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn edit_without_value_is_not_a_key() {
+        assert!(ksp_cfg_formatter::parse_to_ast("PART\n{\n\t@mass\n}\n").is_err());
+    }
+}
@@ -0,0 +1,60 @@
+//! Locating text that borrows from the contents of a file, such as the anchors of lints and
+//! diagnostics.
+
+/// A 1-based position within a file. Columns count characters.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Location {
+    /// The position of `anchor` within `source`, or `None` if `anchor` does not borrow from it.
+    pub fn of(source: &str, anchor: &str) -> Option<Self> {
+        Self::at(source, offset(source, anchor)?)
+    }
+
+    /// The position of the byte `offset` within `source`, or `None` if it is out of bounds or not
+    /// at a character boundary.
+    pub fn at(source: &str, offset: usize) -> Option<Self> {
+        let before = source.get(..offset)?;
+        let line_start = line_start(source, offset);
+        Some(Self {
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+        })
+    }
+}
+
+/// The byte offset of `anchor` within `source`, or `None` if `anchor` does not borrow from it.
+pub fn offset(source: &str, anchor: &str) -> Option<usize> {
+    let offset = (anchor.as_ptr() as usize).checked_sub(source.as_ptr() as usize)?;
+    (offset + anchor.len() <= source.len()).then_some(offset)
+}
+
+/// The byte offset of the start of the line containing the byte `offset`.
+pub fn line_start(source: &str, offset: usize) -> usize {
+    source[..offset].rfind('\n').map_or(0, |idx| idx + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locates_anchors() {
+        let source = "PART\n{\n\tnäme = a\n}";
+        let anchor = &source[source.find('=').unwrap()..][..1];
+        assert_eq!(offset(source, anchor), Some(14));
+        assert_eq!(
+            Location::of(source, anchor),
+            Some(Location { line: 3, column: 7 })
+        );
+        assert_eq!(
+            Location::of(source, &source[source.len()..]).unwrap().line,
+            4
+        );
+        assert_eq!(Location::of(source, "="), None);
+        assert_eq!(offset(source, "PART"), None);
+    }
+}
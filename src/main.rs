@@ -1,56 +1,441 @@
-use std::ffi::OsStr;
-use std::path::PathBuf;
-use std::rc::Rc;
+use std::io::Write;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
 
-use clap::Parser;
-use module_manager_rs::file::File;
-use module_manager_rs::module_manager::ModuleManager;
-use module_manager_rs::raw_patch::RawPatches;
-use walkdir::WalkDir;
+use anyhow::{bail, Context};
+use clap::{Parser, Subcommand, ValueEnum};
+use ksp_cfg_formatter::parser::NodeItem;
+use module_manager_rs::database::Database;
+use module_manager_rs::diff::DatabaseDiff;
+use module_manager_rs::game_data::GameData;
+use module_manager_rs::location::Location;
+use module_manager_rs::module_manager::operator::needs::Installed;
+use module_manager_rs::module_manager::{patcher, ModuleManager, PassReport, Pruned};
+use module_manager_rs::node_patch::NodePatch;
+use module_manager_rs::pass::Pass;
+use module_manager_rs::validate::Severity;
+use module_manager_rs::writer::{Indent, Writer};
 
 #[derive(Parser, Debug)]
 #[command()]
 struct Arguments {
-    game_data: PathBuf,
+    #[command(subcommand)]
+    command: Command,
+    /// The most detailed level of log messages to print.
+    #[arg(long, global = true, default_value = "info")]
+    log_level: log::LevelFilter,
+    /// Write the output to this file instead of stdout. For `patch --format tree`, this is the
+    /// directory to write the files into.
+    #[arg(short, long, global = true)]
+    output: Option<PathBuf>,
+    /// Register an additional installed mod, as a DLL would. May be given multiple times.
+    #[arg(long = "mod", global = true)]
+    mods: Vec<String>,
 }
 
-fn main() -> anyhow::Result<()> {
-    pretty_env_logger::formatted_builder()
-        .filter_level(log::LevelFilter::Info)
-        .init();
-    let args = Arguments::parse();
-    let full_path = args.game_data.canonicalize()?;
-    log::info!("GameData path: {full_path:?}");
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Patch a GameData directory and write the resulting database.
+    Patch {
+        game_data: PathBuf,
+        #[arg(long, value_enum, default_value_t = Format::Cache)]
+        format: Format,
+        /// Indent with this many spaces instead of tabs.
+        #[arg(long)]
+        indent_spaces: Option<usize>,
+        /// Write a snapshot of the database after each pass into this directory.
+        #[arg(long)]
+        dump_passes: Option<PathBuf>,
+        /// Stop after the given pass, e.g. `:FOR[RealismOverhaul]`.
+        #[arg(long)]
+        stop_after: Option<Pass<'static>>,
+    },
+    /// Parse and validate the patches of a GameData directory without executing them.
+    Check { game_data: PathBuf },
+    /// Check the patches of a GameData directory for likely mistakes without executing them.
+    Lint { game_data: PathBuf },
+    /// Print the nodes of the patched database that a patch header targets.
+    Query {
+        /// A GameData directory or ConfigCache file.
+        source: PathBuf,
+        /// A patch header, e.g. `PART[fuel*|tank]:HAS[#mass]`. Names may contain the wildcards `*`
+        /// and `?`, and so may the types, keys and values of `:HAS` predicates.
+        selector: String,
+        /// Emit the nodes as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Compare the databases produced by two GameData directories or ConfigCache files.
+    Diff {
+        old: PathBuf,
+        new: PathBuf,
+        /// Emit the difference as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Patch a GameData directory, and patch it again whenever its files change. The database is
+    /// written after each run.
+    #[cfg(feature = "watch")]
+    Watch {
+        game_data: PathBuf,
+        #[arg(long, value_enum, default_value_t = Format::Cache)]
+        format: Format,
+    },
+    /// List the passes of a GameData directory in execution order, with their patch counts and
+    /// pruned passes.
+    Passes {
+        game_data: PathBuf,
+        /// Emit the passes as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Format {
+    /// A `ModuleManager.ConfigCache` file.
+    Cache,
+    Json,
+    /// One cfg file per original file, mirroring the layout of GameData.
+    Tree,
+}
+
+/// The source of a database: either a GameData directory to be patched, or a ConfigCache produced
+/// by ModuleManager.
+enum Source {
+    GameData(GameData),
+    ConfigCache(String),
+}
 
-    let file_storage = {
-        let mut file_storage = vec![];
+impl Source {
+    fn load(path: &Path) -> anyhow::Result<Self> {
+        let full_path = path.canonicalize()?;
+        if full_path.is_file() {
+            log::info!("ConfigCache path: {full_path:?}");
+            Ok(Self::ConfigCache(std::fs::read_to_string(full_path)?))
+        } else {
+            log::info!("GameData path: {full_path:?}");
+            Ok(Self::GameData(GameData::load(&full_path)?))
+        }
+    }
 
-        for entry in WalkDir::new(full_path).sort_by_file_name() {
-            let cfg = entry?.into_path();
-            // TODO: ignore PluginData.
-            if !cfg.is_file() || cfg.extension() != Some(OsStr::new("cfg")) {
-                continue;
+    /// Patch or read the database. `stop_after` is the pass that `observer` stops execution
+    /// after, which is checked to exist beforehand.
+    fn database<'a>(
+        &'a self,
+        mods: &'a [String],
+        stop_after: Option<&Pass>,
+        observer: impl FnMut(&Pass<'a>, &Database<'a>) -> ControlFlow<()>,
+    ) -> anyhow::Result<Database<'a>> {
+        match self {
+            Self::GameData(game_data) => {
+                let patcher = game_data.module_manager(mods.iter().map(String::as_str))?;
+                if let Some(pass) = stop_after {
+                    check_pass_runs(&patcher, pass)?;
+                }
+                Ok(patcher.execute_with(observer)?)
             }
-            file_storage.push(File::new(Rc::from(&*cfg), std::fs::read_to_string(cfg)?));
+            Self::ConfigCache(_) if stop_after.is_some() => {
+                bail!("`--stop-after` requires a GameData directory")
+            }
+            Self::ConfigCache(contents) => Ok(Database::from_config_cache(
+                ksp_cfg_formatter::parse_to_ast(contents)?,
+            )?),
         }
-        file_storage
-    };
-    let raw_patches = {
-        let mut raw_patches = RawPatches::default();
-        for cfg in &file_storage {
-            log::info!("parsing {:?}", cfg.path);
-            raw_patches.files.push(File::new(
-                Rc::clone(&cfg.path),
-                ksp_cfg_formatter::parse_to_ast(&cfg.contents)?,
-            ))
-        }
-        raw_patches
+    }
+}
+
+/// Fail if `pass` does not run, as stopping after it would silently run every pass instead.
+fn check_pass_runs(mm: &ModuleManager, pass: &Pass) -> anyhow::Result<()> {
+    if mm.passes().any(|candidate| candidate == pass) {
+        return Ok(());
+    }
+    let reason = mm.pruned().iter().find_map(|pruned| match pruned {
+        Pruned::Pass {
+            pass: candidate,
+            reason,
+        } if candidate == pass => Some(reason),
+        _ => None,
+    });
+    match reason {
+        Some(reason) => bail!("the pass {pass} is skipped: {reason}"),
+        None => bail!("the pass {pass} does not exist"),
+    }
+}
+
+fn no_observer(_: &Pass, _: &Database) -> ControlFlow<()> {
+    ControlFlow::Continue(())
+}
+
+/// Write `contents` to the output file, or to stdout if there is none.
+fn emit(output: Option<&Path>, contents: &str) -> anyhow::Result<()> {
+    match output {
+        Some(path) => std::fs::write(path, contents)
+            .with_context(|| format!("failed to write `{}`", path.to_string_lossy())),
+        None => Ok(std::io::stdout().lock().write_all(contents.as_bytes())?),
+    }
+}
+
+/// Wrap a patch header such as `PART[foo]:HAS[#mass]`, with or without the `@` operator, into an
+/// empty edit patch.
+fn selector_cfg(selector: &str) -> String {
+    format!(
+        "@{}\n{{\n}}\n",
+        selector.strip_prefix('@').unwrap_or(selector)
+    )
+}
+
+fn parse_selector(cfg: &str) -> anyhow::Result<NodePatch<'_>> {
+    let document = ksp_cfg_formatter::parse_to_ast(cfg)?;
+    let Some(NodeItem::Node(node)) = document.statements.into_iter().next() else {
+        bail!("expected a node");
     };
+    // Like a patch, a selector matches node types exactly.
+    if node.identifier.contains(['*', '?']) {
+        bail!("node types cannot contain wildcards");
+    }
+    Ok(NodePatch::from_cst(node, true)?)
+}
+
+/// Write `database` in `format` to the output file, or the output directory for a tree.
+fn write_database(
+    database: &Database,
+    writer: &Writer,
+    format: Format,
+    output: Option<&Path>,
+) -> anyhow::Result<()> {
+    match format {
+        Format::Cache => {
+            let mut cache = String::new();
+            writer.write_config_cache(&mut cache, database)?;
+            emit(output, &cache)
+        }
+        Format::Json => emit(output, &format!("{:#}\n", database.to_json())),
+        Format::Tree => {
+            let output = output.context("`--format tree` requires `--output`")?;
+            Ok(database.export(output, writer)?)
+        }
+    }
+}
+
+#[cfg(feature = "watch")]
+fn watch(
+    game_data: &Path,
+    format: Format,
+    output: Option<&Path>,
+    mods: &[String],
+) -> anyhow::Result<()> {
+    use std::time::Duration;
+
+    use module_manager_rs::incremental::Incremental;
+    use notify::{RecursiveMode, Watcher};
+
+    /// Changes arriving within this time of each other are handled together, as editors often
+    /// write a file in several steps.
+    const DEBOUNCE: Duration = Duration::from_millis(200);
+
+    let root = game_data.canonicalize()?;
+    let (sender, events) = std::sync::mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    watcher.watch(&root, RecursiveMode::Recursive)?;
 
-    let patcher = ModuleManager::new(raw_patches, std::iter::empty())?;
-    let database = patcher.execute()?;
+    let mut incremental = Incremental::load(&root, mods)?;
+    if let Some(database) = incremental.database() {
+        write_database(database, &Writer::default(), format, output)?;
+    }
+    log::info!("watching {root:?} for changes");
+    while let Ok(event) = events.recv() {
+        let mut changed = vec![];
+        let mut event = Some(event);
+        while let Some(next) = event {
+            match next {
+                Ok(next) => changed.extend(next.paths),
+                // The watcher may recover, e.g. after the event queue overflowed, and the
+                // next change is picked up along with any files whose changes were missed.
+                Err(e) => log::error!("failed to watch {root:?}: {e}"),
+            }
+            event = events.recv_timeout(DEBOUNCE).ok();
+        }
+        let update = incremental.update(changed);
+        // Keep watching, so that mistakes can be fixed.
+        for e in &update.errors {
+            log::error!("{e}");
+        }
+        if update.resumed_from.is_some() {
+            if let Some(database) = incremental.database() {
+                write_database(database, &Writer::default(), format, output)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Format the pass report as a table, with the reason next to pruned passes.
+fn pass_table(report: &[PassReport]) -> String {
+    let names = report
+        .iter()
+        .map(|pass| pass.pass.to_string())
+        .collect::<Vec<_>>();
+    let width = names.iter().map(String::len).max().unwrap_or(0).max(4);
+    let mut table = format!("{:width$}  FILES  PATCHES  PRUNED\n", "PASS");
+    for (pass, name) in report.iter().zip(names) {
+        table += &format!(
+            "{name:width$}  {:>5}  {:>7}  {:>6}",
+            pass.files, pass.patches, pass.pruned_patches
+        );
+        if let Some(reason) = &pass.pruned {
+            table += &format!("  skipped: {reason}");
+        }
+        table += "\n";
+    }
+    table
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Arguments::parse();
+    pretty_env_logger::formatted_builder()
+        .filter_level(args.log_level)
+        .init();
+    let output = args.output.as_deref();
 
-    println!("{database}");
+    match args.command {
+        Command::Patch {
+            game_data,
+            format,
+            indent_spaces,
+            dump_passes,
+            stop_after,
+        } => {
+            if let Some(dir) = &dump_passes {
+                std::fs::create_dir_all(dir)?;
+            }
+            let source = Source::load(&game_data)?;
+            let mut pass_idx = 0;
+            let mut dump_error = None;
+            let database = source.database(&args.mods, stop_after.as_ref(), |pass, database| {
+                if let Some(dir) = &dump_passes {
+                    let file_name =
+                        format!("{pass_idx:03}_{pass}.cfg").replace([':', '<', '>'], "");
+                    if let Err(e) = std::fs::write(dir.join(file_name), database.to_string()) {
+                        dump_error = Some(e);
+                        return ControlFlow::Break(());
+                    }
+                }
+                pass_idx += 1;
+                if stop_after.as_ref() == Some(pass) {
+                    ControlFlow::Break(())
+                } else {
+                    ControlFlow::Continue(())
+                }
+            })?;
+            if let Some(e) = dump_error {
+                return Err(e.into());
+            }
+
+            let writer = Writer::new(indent_spaces.map_or(Indent::Tabs, Indent::Spaces));
+            write_database(&database, &writer, format, output)?;
+        }
+        Command::Check { game_data } => {
+            let game_data = GameData::load(&game_data.canonicalize()?)?;
+            let mm = game_data.module_manager(args.mods.iter().map(String::as_str))?;
+            let mut report = String::new();
+            for diagnostic in mm.diagnostics() {
+                report += &format!("{diagnostic}\n");
+            }
+            let count = |severity| {
+                mm.diagnostics()
+                    .iter()
+                    .filter(|diagnostic| diagnostic.severity == severity)
+                    .count()
+            };
+            let (errors, warnings) = (count(Severity::Error), count(Severity::Warning));
+            report += &format!(
+                "checked {} files: {errors} errors, {warnings} warnings, {} pruned\n",
+                game_data.files().len(),
+                mm.pruned().len(),
+            );
+            emit(output, &report)?;
+            if errors > 0 {
+                std::process::exit(1);
+            }
+        }
+        Command::Lint { game_data } => {
+            let game_data = GameData::load(&game_data.canonicalize()?)?;
+            let installed = Installed::new(
+                args.mods.iter().map(String::as_str),
+                game_data.directories(),
+            );
+            let lints = game_data.parse()?.lint(&installed);
+            let mut report = String::new();
+            for lint in &lints {
+                let location = game_data
+                    .files()
+                    .iter()
+                    .find(|file| file.path == lint.path)
+                    .and_then(|file| lint.location(&file.contents));
+                report += &lint.path.to_string_lossy();
+                if let Some(Location { line, column }) = location {
+                    report += &format!(":{line}:{column}");
+                }
+                report += &format!(": {lint}\n");
+            }
+            emit(output, &report)?;
+            if lints.iter().any(|lint| lint.severity == Severity::Error) {
+                std::process::exit(1);
+            }
+        }
+        Command::Query {
+            source,
+            selector,
+            json,
+        } => {
+            let cfg = selector_cfg(&selector);
+            let patch =
+                parse_selector(&cfg).with_context(|| format!("invalid selector `{selector}`"))?;
+            let source = Source::load(&source)?;
+            let database = source.database(&args.mods, None, no_observer)?;
+            let matches = database
+                .nodes()
+                .iter()
+                .filter(|node| patcher::matches(&patch, node))
+                .cloned()
+                .collect::<Database>();
+            log::info!("{} nodes match `{selector}`", matches.nodes().len());
+            if json {
+                emit(output, &format!("{:#}\n", matches.to_json()))?;
+            } else {
+                let mut cache = String::new();
+                Writer::default().write_config_cache(&mut cache, &matches)?;
+                emit(output, &cache)?;
+            }
+        }
+        Command::Diff { old, new, json } => {
+            let (old, new) = (Source::load(&old)?, Source::load(&new)?);
+            let (old, new) = (
+                old.database(&args.mods, None, no_observer)?,
+                new.database(&args.mods, None, no_observer)?,
+            );
+            let diff = DatabaseDiff::new(&old, &new);
+            if json {
+                emit(output, &format!("{:#}\n", diff.to_json()))?;
+            } else {
+                emit(output, &diff.to_string())?;
+            }
+        }
+        #[cfg(feature = "watch")]
+        Command::Watch { game_data, format } => watch(&game_data, format, output, &args.mods)?,
+        Command::Passes { game_data, json } => {
+            let game_data = GameData::load(&game_data.canonicalize()?)?;
+            let mm = game_data.module_manager(args.mods.iter().map(String::as_str))?;
+            let report = mm.pass_report();
+            if json {
+                let json = report.iter().map(PassReport::to_json).collect::<Vec<_>>();
+                emit(output, &format!("{:#}\n", serde_json::Value::from(json)))?;
+            } else {
+                emit(output, &pass_table(&report))?;
+            }
+        }
+    }
 
     Ok(())
 }
@@ -2,44 +2,180 @@ pub mod operator;
 pub mod patcher;
 pub mod searcher;
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::ops::ControlFlow;
+use std::path::Path;
+use std::sync::Arc;
+
+use serde_json::json;
 
 use crate::database::Database;
+use crate::module_manager::operator::needs::Installed;
 use crate::module_manager::patcher::Patcher;
-use crate::pass::Pass;
+use crate::pass::{Pass, PassIdentifier};
 use crate::patch_set::PatchSet;
 use crate::raw_patch::RawPatches;
+use crate::validate::{Diagnostic, Severity};
 use crate::Result;
 
 pub struct ModuleManager<'a> {
-    dll_passes: HashSet<String>,
     patches: PatchSet<'a>,
+    diagnostics: Vec<Diagnostic<'a>>,
+    pruned: Vec<Pruned<'a>>,
     database: Database<'a>,
 }
 
+/// A pass, top-level patch, or item within a patch that was removed before execution.
+#[derive(Clone, Debug)]
+pub enum Pruned<'a> {
+    Pass {
+        pass: Pass<'a>,
+        reason: PruneReason<'a>,
+    },
+    Patch {
+        pass: Pass<'a>,
+        path: Arc<Path>,
+        /// The header of the patch, e.g. `@PART[foo]`.
+        patch: String,
+        reason: PruneReason<'a>,
+    },
+    /// A node or key nested within a patch that is otherwise executed.
+    Child {
+        pass: Pass<'a>,
+        path: Arc<Path>,
+        patch: String,
+        /// The path to the item within the patch, e.g. `@MODULE[foo]/key` or `:HAS[#key]`.
+        item: String,
+        reason: PruneReason<'a>,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub enum PruneReason<'a> {
+    /// The mod that the pass is anchored to is not installed.
+    MissingMod(PassIdentifier<'a>),
+    /// The given clause of the `:NEEDS` expression, e.g. `Foo|!Bar`, is not satisfied.
+    UnsatisfiedNeeds(String),
+}
+
+/// The patches of a single pass, as listed by [`ModuleManager::pass_report`].
+#[derive(Clone, Debug)]
+pub struct PassReport<'a> {
+    pub pass: Pass<'a>,
+    /// The number of files with patches in this pass, including pruned ones.
+    pub files: usize,
+    /// The number of top-level patches that will be executed.
+    pub patches: usize,
+    /// The number of top-level patches that were pruned.
+    pub pruned_patches: usize,
+    /// Why the pass will not be executed at all, if it was pruned.
+    pub pruned: Option<PruneReason<'a>>,
+}
+
 impl<'a> ModuleManager<'a> {
+    /// Prepare to patch `raw_patches`. The mod list consists of `dll_names`, the top-level
+    /// `directories`, and mods that declare a `:FOR` pass. `directories` are relative to GameData,
+    /// and are also used to evaluate `:NEEDS` on subfolders, e.g. `:NEEDS[Squad/Parts]`.
     pub fn new(
         raw_patches: RawPatches<'a>,
         dll_names: impl Iterator<Item = &'a str>,
+        directories: impl Iterator<Item = &'a Path>,
     ) -> Result<Self> {
-        Ok(Self {
-            dll_passes: dll_names.into_iter().map(ToOwned::to_owned).collect(),
-            patches: raw_patches.extract()?,
+        let (patches, diagnostics) = raw_patches.extract_validated()?;
+        for diagnostic in &diagnostics {
+            match diagnostic.severity {
+                Severity::Warning => log::warn!("{diagnostic}"),
+                Severity::Error => log::error!("{diagnostic}"),
+            }
+        }
+        let mut mm = Self {
+            patches,
+            diagnostics,
+            pruned: vec![],
             database: Database::default(),
-        })
+        };
+        let mut installed = Installed::new(dll_names, directories);
+        installed
+            .mods
+            .extend(mm.scan_declared_passes().cloned().collect::<Vec<_>>());
+        mm.insert_mod_passes(&installed.mods);
+        mm.prune_missing_mods(&installed.mods);
+        mm.prune_needs(&installed);
+        Ok(mm)
     }
 
-    pub fn execute(mut self) -> Result<Database<'a>> {
-        let declared_passes = self.scan_declared_passes();
-        let all_existing_passes: HashSet<String> = self
-            .dll_passes
-            .iter()
-            .map(AsRef::as_ref)
-            .chain(declared_passes)
-            .map(ToOwned::to_owned)
-            .collect();
-        self.prune_before_after(&all_existing_passes);
-        self.prune_needs(&all_existing_passes);
+    /// Problems found while validating the patches. Patches with errors are not executed.
+    pub fn diagnostics(&self) -> &[Diagnostic<'a>] {
+        &self.diagnostics
+    }
+
+    /// All passes and patches that were pruned, and will thus not be executed.
+    pub fn pruned(&self) -> &[Pruned<'a>] {
+        &self.pruned
+    }
+
+    /// The passes that will be executed, in order.
+    pub fn passes(&self) -> impl Iterator<Item = &Pass<'a>> {
+        self.patches.iter().map(|(pass, _)| pass)
+    }
+
+    /// Every pass in execution order, including pruned passes at the position they would have run.
+    pub fn pass_report(&self) -> Vec<PassReport<'a>> {
+        let mut pruned_patches = HashMap::<&Pass, (usize, HashSet<&Path>)>::new();
+        for entry in &self.pruned {
+            if let Pruned::Patch { pass, path, .. } = entry {
+                let (patches, files) = pruned_patches.entry(pass).or_default();
+                *patches += 1;
+                files.insert(path);
+            }
+        }
+        let mut pruned_files = |pass| pruned_patches.remove(pass).unwrap_or_default();
+
+        let mut report = vec![];
+        for (pass, files) in self.patches.iter() {
+            let (pruned, mut paths) = pruned_files(pass);
+            paths.extend(
+                files
+                    .iter()
+                    .filter(|file| !file.contents.is_empty())
+                    .map(|file| &*file.path),
+            );
+            report.push(PassReport {
+                pass: pass.clone(),
+                files: paths.len(),
+                patches: files.iter().map(|file| file.contents.len()).sum(),
+                pruned_patches: pruned,
+                pruned: None,
+            });
+        }
+        for entry in &self.pruned {
+            if let Pruned::Pass { pass, reason } = entry {
+                let (pruned, paths) = pruned_files(pass);
+                report.push(PassReport {
+                    pass: pass.clone(),
+                    files: paths.len(),
+                    patches: 0,
+                    pruned_patches: pruned,
+                    pruned: Some(reason.clone()),
+                });
+            }
+        }
+        report.sort_by(|a, b| a.pass.cmp(&b.pass));
+        report
+    }
+
+    pub fn execute(self) -> Result<Database<'a>> {
+        self.execute_with(|_, _| ControlFlow::Continue(()))
+    }
+
+    /// Execute all passes, calling `observer` with the state of the database after each pass.
+    /// Execution stops early if `observer` returns [`ControlFlow::Break`], in which case the
+    /// database as of that pass is returned.
+    pub fn execute_with(
+        mut self,
+        mut observer: impl FnMut(&Pass<'a>, &Database<'a>) -> ControlFlow<()>,
+    ) -> Result<Database<'a>> {
         for (pass, files) in self.patches.iter() {
             log::info!("running pass {pass}");
             for file in files {
@@ -47,43 +183,230 @@ impl<'a> ModuleManager<'a> {
                     Patcher::new(&mut self.database, file.path.clone(), patch).evaluate()?;
                 }
             }
+            if observer(pass, &self.database).is_break() {
+                log::info!("stopping after pass {pass}");
+                break;
+            }
         }
         Ok(self.database)
     }
 
-    fn scan_declared_passes(&'a self) -> impl Iterator<Item = &'a str> {
+    /// Execute the passes from `first` on, starting from `database`, which must be the state of
+    /// the database after the passes before `first`. See [`Self::execute_with`].
+    pub fn execute_from(
+        mut self,
+        database: Database<'a>,
+        first: &Pass<'a>,
+        observer: impl FnMut(&Pass<'a>, &Database<'a>) -> ControlFlow<()>,
+    ) -> Result<Database<'a>> {
+        let skipped = self
+            .patches
+            .iter()
+            .take_while(|(pass, _)| pass < first)
+            .count();
+        self.patches.0.drain(..skipped);
+        self.database = database;
+        self.execute_with(observer)
+    }
+
+    /// The patches that will be executed, by pass.
+    pub fn patch_set(&self) -> &PatchSet<'a> {
+        &self.patches
+    }
+
+    fn scan_declared_passes(&self) -> impl Iterator<Item = &PassIdentifier<'a>> {
         log::info!("scanning declared passes");
         self.patches.iter().filter_map(|(pass, _)| {
             if let Pass::For(ident) = pass {
-                Some(&*ident.0)
+                Some(ident)
             } else {
                 None
             }
         })
     }
 
-    fn prune_before_after(&mut self, declared_passes: &HashSet<String>) {
-        self.patches.0.retain(|(pass, _)| match pass {
-            // N.B.: the :LAST[ident] passes are not anchored to declared passes, but are merely
-            // naked identifiers used for sorting.
-            Pass::Default | Pass::First | Pass::For(_) | Pass::Last(_) | Pass::Final => true,
-            pass @ (Pass::Before(ident) | Pass::After(ident)) => {
-                let exists = declared_passes.contains(&*ident.0);
+    /// MM allocates `:BEFORE`, `:FOR`, and `:AFTER` slots to every mod in its mod list, including
+    /// those registered by DLLs that have no `:FOR` patches of their own. Insert empty `:FOR`
+    /// passes for such mods so that the pass sequence is complete.
+    fn insert_mod_passes(&mut self, declared_passes: &HashSet<PassIdentifier<'a>>) {
+        for ident in declared_passes {
+            self.patches.insert_pass(Pass::For(ident.clone()));
+        }
+    }
+
+    /// Remove `:BEFORE`, `:AFTER`, and `:LAST` passes whose mod does not exist.
+    fn prune_missing_mods(&mut self, declared_passes: &HashSet<PassIdentifier>) {
+        let pruned = &mut self.pruned;
+        self.patches.0.retain(|(pass, files)| match pass {
+            Pass::Insert | Pass::Default | Pass::First | Pass::For(_) | Pass::Final => true,
+            Pass::Before(ident) | Pass::After(ident) | Pass::Last(ident) => {
+                let exists = declared_passes.contains(ident);
                 if !exists {
-                    log::info!("pruning pass {pass}, as :FOR[{ident}] does not exist");
+                    let reason = PruneReason::MissingMod(ident.clone());
+                    record(
+                        pruned,
+                        Pruned::Pass {
+                            pass: pass.clone(),
+                            reason: reason.clone(),
+                        },
+                    );
+                    for file in files {
+                        for patch in &file.contents {
+                            record(
+                                pruned,
+                                Pruned::Patch {
+                                    pass: pass.clone(),
+                                    path: file.path.clone(),
+                                    patch: patch.to_string(),
+                                    reason: reason.clone(),
+                                },
+                            );
+                        }
+                    }
                 }
                 exists
             }
         })
     }
 
-    fn prune_needs(&mut self, declared_passes: &HashSet<String>) {
+    fn prune_needs(&mut self, installed: &Installed) {
         log::info!("evaluating :NEEDS");
-        for (_, files) in self.patches.iter_mut() {
+        for (pass, files) in self.patches.iter_mut() {
             for file in files {
-                file.contents
-                    .retain_mut(|node| operator::needs::prune_node_recurse(node, declared_passes))
+                file.contents.retain_mut(|node| {
+                    let mut pruned_children = vec![];
+                    let unsatisfied =
+                        operator::needs::prune_node_recurse(node, installed, &mut pruned_children);
+                    if let Some(clause) = unsatisfied {
+                        record(
+                            &mut self.pruned,
+                            Pruned::Patch {
+                                pass: pass.clone(),
+                                path: file.path.clone(),
+                                patch: node.to_string(),
+                                reason: PruneReason::UnsatisfiedNeeds(clause),
+                            },
+                        );
+                        return false;
+                    }
+                    for child in pruned_children {
+                        record(
+                            &mut self.pruned,
+                            Pruned::Child {
+                                pass: pass.clone(),
+                                path: file.path.clone(),
+                                patch: node.to_string(),
+                                item: child.item,
+                                reason: PruneReason::UnsatisfiedNeeds(child.clause),
+                            },
+                        );
+                    }
+                    true
+                })
             }
         }
     }
 }
+
+fn record<'a>(pruned: &mut Vec<Pruned<'a>>, entry: Pruned<'a>) {
+    log::info!("{entry}");
+    pruned.push(entry);
+}
+
+impl<'a> Display for Pruned<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Pass { pass, reason } => write!(f, "pruned pass {pass}: {reason}"),
+            Self::Patch {
+                pass,
+                path,
+                patch,
+                reason,
+            } => write!(
+                f,
+                "pruned patch {patch}{pass} in {}: {reason}",
+                path.to_string_lossy()
+            ),
+            Self::Child {
+                pass,
+                path,
+                patch,
+                item,
+                reason,
+            } => write!(
+                f,
+                "pruned `{item}` from patch {patch}{pass} in {}: {reason}",
+                path.to_string_lossy()
+            ),
+        }
+    }
+}
+
+impl<'a> PassReport<'a> {
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "pass": self.pass.to_string(),
+            "files": self.files,
+            "patches": self.patches,
+            "pruned_patches": self.pruned_patches,
+            "pruned": self.pruned.as_ref().map(ToString::to_string),
+        })
+    }
+}
+
+impl<'a> Display for PruneReason<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingMod(ident) => write!(f, "mod `{ident}` does not exist"),
+            Self::UnsatisfiedNeeds(clause) => write!(f, ":NEEDS[{clause}] is not satisfied"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use crate::file::File;
+    use crate::game_data::GameData;
+    use crate::pass::Pass;
+
+    #[test]
+    fn pass_report() {
+        let file =
+            |path: &str, contents: &str| File::new(Arc::from(Path::new(path)), contents.to_owned());
+        let game_data = GameData::from_files(vec![
+            file("ModA/parts.cfg", "PART\n{\n}\n@PART:FOR[ModA]\n{\n}\n"),
+            file(
+                "ModA/patches.cfg",
+                "@PART:AFTER[Missing]\n{\n}\n@PART:NEEDS[Missing]\n{\n}\n",
+            ),
+            file("ModB/patches.cfg", "@PART:AFTER[Missing]\n{\n}\n"),
+        ]);
+        let mm = game_data.module_manager(std::iter::empty()).unwrap();
+        let report = mm
+            .pass_report()
+            .into_iter()
+            .map(|pass| {
+                (
+                    pass.pass.to_string(),
+                    pass.files,
+                    pass.patches,
+                    pass.pruned_patches,
+                    pass.pruned.is_some(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            report,
+            [
+                (Pass::Insert.to_string(), 1, 1, 0, false),
+                (Pass::Default.to_string(), 1, 0, 1, false),
+                (":AFTER[Missing]".to_owned(), 2, 0, 2, true),
+                (":FOR[ModA]".to_owned(), 1, 1, 0, false),
+                (":FOR[ModB]".to_owned(), 0, 0, 0, false),
+            ]
+        );
+    }
+}
@@ -10,15 +10,125 @@ pub fn is_satisfied(node: &ConfigNode, patch: &NodePatch) -> bool {
         .all(|predicate| evaluate_predicate(node, predicate))
 }
 
+/// `@NODE[name]` and `!NODE[name]` require a child node to exist or not, optionally with its own
+/// `:HAS`. `#key[value]` and `~key[value]` do the same for keys. Types, names, keys and values may
+/// contain wildcards.
+///
+/// Predicates with an unsatisfied `:NEEDS` are removed before execution, along with other nested
+/// items; see [`prune_node_recurse`](super::needs::prune_node_recurse).
 pub fn evaluate_predicate(node: &ConfigNode, predicate: &HasPredicate) -> bool {
-    true
+    match predicate {
+        HasPredicate::NodePredicate {
+            negated,
+            node_type,
+            name,
+            has_block,
+            ..
+        } => {
+            let found = node.nodes.iter().any(|child| {
+                wildcard_matches(node_type, &child.ident)
+                    && name.is_none_or(|name| {
+                        child
+                            .name_key()
+                            .is_some_and(|child_name| wildcard_matches(name, child_name))
+                    })
+                    && has_block.as_ref().is_none_or(|has| {
+                        has.predicates
+                            .iter()
+                            .all(|predicate| evaluate_predicate(child, predicate))
+                    })
+            });
+            *negated ^ found
+        }
+        HasPredicate::KeyPredicate {
+            negated,
+            key,
+            value,
+            ..
+        } => {
+            let found = node.keys.iter().any(|candidate| {
+                wildcard_matches(key, &candidate.ident)
+                    && value.is_none_or(|value| wildcard_matches(value, &candidate.value))
+            });
+            *negated ^ found
+        }
+    }
+}
+
+/// Displays a predicate the way it is written, e.g. `#key[value]`.
+pub fn fmt_predicate(predicate: &HasPredicate) -> String {
+    let (operator, ident, arg) = match predicate {
+        HasPredicate::NodePredicate {
+            negated,
+            node_type,
+            name,
+            ..
+        } => (if *negated { "!" } else { "@" }, node_type, name),
+        HasPredicate::KeyPredicate {
+            negated,
+            key,
+            value,
+            ..
+        } => (if *negated { "~" } else { "#" }, key, value),
+    };
+    match arg {
+        Some(arg) => format!("{operator}{ident}[{arg}]"),
+        None => format!("{operator}{ident}"),
+    }
 }
 
 pub fn name_matches(node: &ConfigNode, patch: &NodePatch) -> bool {
     // TODO: surface error for OR-matching at non-top-level.
     match (&patch.target_name, node.name_key()) {
-        (Some(targets), Some(name)) => targets.iter().any(|&target| target == name),
+        (Some(targets), Some(name)) => targets.iter().any(|&target| wildcard_matches(target, name)),
         (Some(_), None) => false,
         (None, _) => true,
     }
 }
+
+/// Match `text` against `pattern`, where `*` matches any sequence of characters and `?` matches a
+/// single character.
+pub fn wildcard_matches(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+    // The position after the last `*`, and the text position it was matched up to.
+    let mut star = None;
+    let (mut p, mut t) = (0, 0);
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((after_star, matched)) => {
+                    p = after_star;
+                    t = matched + 1;
+                    star = Some((after_star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcards() {
+        assert!(wildcard_matches("*", ""));
+        assert!(wildcard_matches("Module*", "ModuleEngines"));
+        assert!(wildcard_matches("*Engines*FX", "ModuleEnginesFX"));
+        assert!(wildcard_matches("a?c", "abc"));
+        assert!(!wildcard_matches("a?c", "ac"));
+        assert!(!wildcard_matches("Module*", "PartModule"));
+        assert!(wildcard_matches("a*b*c", "aXbYbZc"));
+    }
+}
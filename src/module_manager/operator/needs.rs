@@ -1,35 +1,172 @@
 use std::collections::HashSet;
+use std::path::Path;
 
-use ksp_cfg_formatter::parser::{self, OrClause};
+use itertools::Itertools;
+use ksp_cfg_formatter::parser::{self, HasPredicate, OrClause};
 
+use super::has;
 use crate::node_patch::NodePatch;
+use crate::pass::PassIdentifier;
 
+/// What `:NEEDS` are evaluated against.
+#[derive(Clone, Debug, Default)]
+pub struct Installed<'a> {
+    /// The mod list: DLLs, top-level GameData directories, and mods that declare a `:FOR` pass.
+    pub mods: HashSet<PassIdentifier<'a>>,
+    /// Every directory in GameData, e.g. `Squad/Parts`, with `/` as the separator.
+    pub directories: HashSet<PassIdentifier<'a>>,
+}
+
+impl<'a> Installed<'a> {
+    /// The mod list consists of `dll_names` and the top-level `directories`, which are relative to
+    /// GameData. Mods that declare a `:FOR` pass must be added separately.
+    pub fn new(
+        dll_names: impl Iterator<Item = &'a str>,
+        directories: impl Iterator<Item = &'a Path>,
+    ) -> Self {
+        let mut installed = Self::default();
+        for directory in directories {
+            let components = directory
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>();
+            if let [mod_folder] = components.as_slice() {
+                installed.mods.insert(PassIdentifier(mod_folder.clone()));
+            }
+            installed
+                .directories
+                .insert(PassIdentifier::from(components.join("/")));
+        }
+        installed.mods.extend(dll_names.map(PassIdentifier::from));
+        installed
+    }
+}
+
+/// A nested node or key that was removed because its `:NEEDS` was not satisfied.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct PrunedChild {
+    /// The path to the item from its top-level patch, e.g. `@MODULE[foo]/key`, or
+    /// `@MODULE[foo]:HAS[#key]` for a `:HAS` predicate.
+    pub item: String,
+    /// The clause of the item's `:NEEDS` that was not satisfied, e.g. `Foo|!Bar`.
+    pub clause: String,
+}
+
+/// Evaluate the `:NEEDS` of a node, then recursively those of its child nodes and keys. Children
+/// that are removed are recorded in `pruned_children`.
+///
 /// # Returns:
-/// Whether this node should be **kept**.
-pub fn prune_node_recurse(node: &mut NodePatch, declared_passes: &HashSet<String>) -> bool {
-    if !is_satisfied(&node.needs, declared_passes) {
-        return false;
+/// `None` if this node should be **kept**, or the unsatisfied clause if it should be removed.
+pub fn prune_node_recurse(
+    node: &mut NodePatch,
+    installed: &Installed,
+    pruned_children: &mut Vec<PrunedChild>,
+) -> Option<String> {
+    if let Some(clause) = unsatisfied_clause(&node.needs, installed) {
+        return Some(fmt_clause(clause));
     }
-    node.node_patches
-        .retain_mut(|child| prune_node_recurse(child, declared_passes));
-    node.key_patches
-        .retain(|child| is_satisfied(&child.needs, declared_passes));
-    true
+    prune_predicates(&mut node.has, "", installed, pruned_children);
+    prune_children(node, "", installed, pruned_children);
+    None
 }
 
-pub fn is_satisfied(needs: &[OrClause], declared_passes: &HashSet<String>) -> bool {
-    needs.iter().all(|or| {
-        or.mod_clauses
+/// Remove `:HAS` predicates whose `:NEEDS` is not satisfied, including those nested in node
+/// predicates. `prefix` is the path to the patch that the predicates belong to.
+fn prune_predicates(
+    predicates: &mut Vec<HasPredicate>,
+    prefix: &str,
+    installed: &Installed,
+    pruned_children: &mut Vec<PrunedChild>,
+) {
+    predicates.retain_mut(|predicate| {
+        let item = format!("{prefix}:HAS[{}]", has::fmt_predicate(predicate));
+        let (needs, nested) = match predicate {
+            HasPredicate::NodePredicate {
+                needs, has_block, ..
+            } => (needs, has_block.as_mut()),
+            HasPredicate::KeyPredicate { needs, .. } => (needs, None),
+        };
+        let needs = needs.as_ref().map_or(&[][..], |needs| &needs.or_clauses);
+        if let Some(clause) = unsatisfied_clause(needs, installed) {
+            pruned_children.push(PrunedChild {
+                item,
+                clause: fmt_clause(clause),
+            });
+            return false;
+        }
+        if let Some(nested) = nested {
+            prune_predicates(&mut nested.predicates, &item, installed, pruned_children);
+        }
+        true
+    });
+}
+
+fn prune_children(
+    node: &mut NodePatch,
+    prefix: &str,
+    installed: &Installed,
+    pruned_children: &mut Vec<PrunedChild>,
+) {
+    node.node_patches.retain_mut(|child| {
+        let item = format!("{prefix}{child}");
+        if let Some(clause) = unsatisfied_clause(&child.needs, installed) {
+            pruned_children.push(PrunedChild {
+                item,
+                clause: fmt_clause(clause),
+            });
+            return false;
+        }
+        prune_predicates(&mut child.has, &item, installed, pruned_children);
+        prune_children(child, &format!("{item}/"), installed, pruned_children);
+        true
+    });
+    node.key_patches.retain(|child| {
+        let Some(clause) = unsatisfied_clause(&child.needs, installed) else {
+            return true;
+        };
+        pruned_children.push(PrunedChild {
+            item: format!("{prefix}{}{}", child.operation, child.ident),
+            clause: fmt_clause(clause),
+        });
+        false
+    });
+}
+
+pub fn is_satisfied(needs: &[OrClause], installed: &Installed) -> bool {
+    unsatisfied_clause(needs, installed).is_none()
+}
+
+/// A `:NEEDS` expression is a conjunction (`&` or `,`) of disjunctions (`|`). Find the first
+/// disjunction that is not satisfied.
+pub fn unsatisfied_clause<'b, 'a>(
+    needs: &'b [OrClause<'a>],
+    installed: &Installed,
+) -> Option<&'b OrClause<'a>> {
+    needs.iter().find(|or| {
+        !or.mod_clauses
             .iter()
-            .any(|need| evaluate_mod_need(need, declared_passes))
+            .any(|need| evaluate_mod_need(need, installed))
     })
 }
 
-pub fn evaluate_mod_need(need: &parser::ModClause, declared_passes: &HashSet<String>) -> bool {
+/// A need containing a `/` refers to a directory within GameData, which must exist. Otherwise, it
+/// refers to a mod.
+pub fn evaluate_mod_need(need: &parser::ModClause, installed: &Installed) -> bool {
     let exists = if need.name.contains('/') {
-        todo!("subfolder :NEEDS are not yet implemented")
+        let directory = need.name.trim_matches('/');
+        installed
+            .directories
+            .contains(&PassIdentifier::from(directory))
     } else {
-        declared_passes.contains(need.name)
+        installed.mods.contains(&PassIdentifier::from(need.name))
     };
     need.negated ^ exists
 }
+
+pub fn fmt_clause(clause: &OrClause) -> String {
+    clause
+        .mod_clauses
+        .iter()
+        .map(|need| format!("{}{}", if need.negated { "!" } else { "" }, need.name))
+        .join("|")
+}
@@ -1,8 +1,8 @@
 use std::path::Path;
-use std::rc::Rc;
+use std::sync::Arc;
 
 use super::operator;
-use super::searcher::Searcher;
+use super::searcher::{Lookup, NodeStore, Searcher};
 use crate::config_node::{ConfigKey, ConfigNode};
 use crate::database::Database;
 use crate::node_patch::NodePatch;
@@ -10,10 +10,9 @@ use crate::operation::Op;
 use crate::Result;
 
 pub struct Patcher<'a, 'b> {
-    file_path: Rc<Path>,
+    file_path: Arc<Path>,
     patch: &'b NodePatch<'a>,
     database: &'b mut Database<'a>,
-    parents: Vec<ConfigNode<'a>>,
 }
 
 impl<'a, 'b> Patcher<'a, 'b>
@@ -22,14 +21,13 @@ where
 {
     pub fn new(
         database: &'b mut Database<'a>,
-        file_path: Rc<Path>,
+        file_path: Arc<Path>,
         top_level_patch: &'b NodePatch<'a>,
     ) -> Self {
         Self {
             file_path,
             database,
             patch: top_level_patch,
-            parents: Vec::new(),
         }
     }
 
@@ -39,7 +37,7 @@ where
                 let mut node = evaluate_node_as_pure_data(self.file_path.clone(), self.patch)?;
                 node.file_path = Some(self.file_path.clone());
                 // TODO: insertion order.
-                self.database.0.push(Some(node));
+                self.database.insert(node)?;
             }
             Op::Rename => {
                 rt_error!(CannotRenameNode @ self.file_path)?;
@@ -47,46 +45,50 @@ where
             Op::CopyFrom { .. } => {}
             Op::Copy | Op::Edit | Op::Delete | Op::EditOrCreate | Op::DefaultValue => {
                 let mut searcher = make_searcher(self.patch);
-                while let Some((handle, mut target)) = searcher.search(&mut self.database.0)? {
+                // Copies are added once the search is over, so that they are not matched again.
+                let mut copies = vec![];
+                while let Some((handle, mut target)) = searcher.search(self.database)? {
                     match &self.patch.operation {
                         Op::Copy => {
-                            let copy = target.clone();
-                            searcher = handle.replace(&mut self.database.0, target)?;
-                            searcher.push(&mut self.database.0, copy)?;
                             // TODO: run inside of copy.
+                            copies.push(target.clone());
+                            searcher = handle.replace(self.database, target)?;
                         }
                         Op::Edit => {
-                            target = self.evaluate_recurse(self.patch, target)?;
-                            searcher = handle.replace(&mut self.database.0, target)?;
+                            // The target is put back even if the patch fails.
+                            let result = self.evaluate_recurse(self.patch, &mut target);
+                            searcher = handle.replace(self.database, target)?;
+                            result?;
                         }
                         Op::EditOrCreate => {
-                            searcher = handle.replace(&mut self.database.0, target)?;
+                            searcher = handle.replace(self.database, target)?;
                         }
                         Op::DefaultValue => {
-                            searcher = handle.replace(&mut self.database.0, target)?;
+                            searcher = handle.replace(self.database, target)?;
                         }
                         Op::Delete => {
-                            searcher = handle.delete(&mut self.database.0)?;
+                            searcher = handle.delete(self.database)?;
                         }
                         Op::Insert | Op::Rename | Op::CopyFrom { .. } => unreachable!(),
                     }
                 }
+                for copy in copies {
+                    self.database.insert(copy)?;
+                }
+                self.database.compact();
             }
         }
         Ok(())
     }
 
-    fn evaluate_recurse(
-        &mut self,
-        patch: &NodePatch<'a>,
-        mut node: ConfigNode<'a>,
-    ) -> Result<ConfigNode<'a>> {
+    fn evaluate_recurse(&mut self, patch: &NodePatch<'a>, node: &mut ConfigNode<'a>) -> Result {
         for node_patch in &patch.node_patches {
             if node_patch.operation == Op::Insert {
                 // TODO: recurse??
-                let child = evaluate_node_as_pure_data(self.file_path.clone(), node_patch)?;
+                let mut child = evaluate_node_as_pure_data(self.file_path.clone(), node_patch)?;
                 // TODO: insertion order.
-                node.nodes.push(Some(child));
+                child.keys_before = node.keys.len();
+                node.nodes.push(child);
                 continue;
             }
             let mut searcher = make_searcher(node_patch);
@@ -96,9 +98,8 @@ where
                     Op::Copy => {
                         let mut copy = target.clone();
                         searcher = handle.replace(&mut node.nodes, target)?;
-                        self.parents.push(node);
-                        copy = self.evaluate_recurse(node_patch, copy)?;
-                        node = self.parents.pop().unwrap();
+                        self.evaluate_recurse(node_patch, &mut copy)?;
+                        copy.keys_before = node.keys.len();
                         searcher.push(&mut node.nodes, copy)?;
                         // TODO: indexing. What happens when copying a wildcard index?
                         break;
@@ -107,10 +108,9 @@ where
                         searcher = handle.replace(&mut node.nodes, target)?;
                     }
                     Op::Edit => {
-                        self.parents.push(node);
-                        target = self.evaluate_recurse(node_patch, target)?;
-                        node = self.parents.pop().unwrap();
+                        let result = self.evaluate_recurse(node_patch, &mut target);
                         searcher = handle.replace(&mut node.nodes, target)?;
+                        result?;
                     }
                     Op::EditOrCreate => {
                         searcher = handle.replace(&mut node.nodes, target)?;
@@ -126,6 +126,7 @@ where
                     }
                 }
             }
+            node.nodes.compact();
         }
         for key_patch in &patch.key_patches {
             match &key_patch.operation {
@@ -139,27 +140,41 @@ where
                 Op::EditOrCreate => {}
                 Op::DefaultValue => {}
                 Op::Delete => {}
-                Op::Rename => node.ident = key_patch.value,
+                Op::Rename => node.ident = key_patch.value.into(),
             }
         }
-        Ok(node)
+        Ok(())
     }
 }
 
+/// Whether `patch` targets `node`, regardless of the patch's operation.
+pub fn matches(patch: &NodePatch, node: &ConfigNode) -> bool {
+    patch.ident == node.ident
+        && operator::has::name_matches(node, patch)
+        && operator::has::is_satisfied(node, patch)
+}
+
 fn make_searcher<'a, 'b>(
     patch: &'b NodePatch<'a>,
 ) -> Searcher<'a, impl FnMut(&ConfigNode<'a>) -> bool + 'b> {
-    Searcher::new(|node| {
-        // TODO: indexing
-        // TODO: name wildcard
-        patch.ident == node.ident
-            && operator::has::name_matches(node, patch)
-            && operator::has::is_satisfied(node, patch)
-    })
+    let needle = |node: &ConfigNode<'a>| matches(patch, node);
+    // Names with wildcards cannot be looked up in an index.
+    let lookup = patch
+        .target_name
+        .as_ref()
+        .filter(|names| !names.iter().any(|name| name.contains(['*', '?'])))
+        .map(|names| Lookup {
+            ident: patch.ident,
+            names: names.clone(),
+        });
+    match lookup {
+        Some(lookup) => Searcher::with_lookup(lookup, needle),
+        None => Searcher::new(needle),
+    }
 }
 
 pub fn evaluate_node_as_pure_data<'a>(
-    path: Rc<Path>,
+    path: Arc<Path>,
     patch: &NodePatch<'a>,
 ) -> Result<ConfigNode<'a>> {
     if patch.operation != Op::Insert {
@@ -167,7 +182,7 @@ pub fn evaluate_node_as_pure_data<'a>(
     }
 
     let mut node = ConfigNode {
-        ident: patch.ident,
+        ident: patch.ident.into(),
         ..Default::default()
     };
 
@@ -180,8 +195,9 @@ pub fn evaluate_node_as_pure_data<'a>(
     }
 
     for child_node_patch in &patch.node_patches {
-        let child_node = evaluate_node_as_pure_data(path.clone(), child_node_patch)?;
-        node.nodes.push(Some(child_node));
+        let mut child_node = evaluate_node_as_pure_data(path.clone(), child_node_patch)?;
+        child_node.keys_before = child_node_patch.keys_before;
+        node.nodes.push(child_node);
     }
 
     Ok(node)
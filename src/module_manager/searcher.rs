@@ -3,9 +3,37 @@ use std::marker::PhantomData;
 use crate::config_node::{ConfigNode, NodeList};
 use crate::{internal_error, PatchingError, Result};
 
+/// A sequence of node slots that a [`Searcher`] walks through. A slot is empty while the node it
+/// holds is being patched, and skipped once the node has been deleted.
+pub trait NodeStore<'a> {
+    /// The position of the first slot at or after `start` that may hold a node matching `lookup`.
+    /// Stores without an index return `start` itself, as long as it is in bounds.
+    fn next_candidate(&self, start: usize, lookup: Option<&Lookup<'a>>) -> Option<usize>;
+    fn slot(&self, idx: usize) -> Option<&ConfigNode<'a>>;
+    fn take(&mut self, idx: usize) -> Option<ConfigNode<'a>>;
+    /// Put `node` into the slot at `idx`, returning the node that previously occupied it.
+    fn replace(&mut self, idx: usize, node: ConfigNode<'a>) -> Option<ConfigNode<'a>>;
+    /// Delete the node in the slot at `idx`, leaving a tombstone. Positions of other slots do not
+    /// change until the store is compacted.
+    fn remove(&mut self, idx: usize) -> Option<ConfigNode<'a>>;
+    fn push(&mut self, node: ConfigNode<'a>);
+    /// Drop tombstones if they make up most of the store, returning whether any slots moved. This
+    /// must not be called while a [`Searcher`] is in use, as it invalidates positions.
+    fn compact(&mut self) -> bool;
+}
+
+/// The exact node type and names that any node matched by a [`Searcher`] has, which allows
+/// indexed stores to skip all other nodes.
+#[derive(Clone, Debug)]
+pub struct Lookup<'a> {
+    pub ident: &'a str,
+    pub names: Vec<&'a str>,
+}
+
 #[derive(Debug)]
 pub struct Searcher<'a, F> {
     needle: F,
+    lookup: Option<Lookup<'a>>,
     next_idx: usize,
     _phantom: PhantomData<&'a mut ()>,
 }
@@ -21,42 +49,53 @@ where
     pub fn new(needle: F) -> Self {
         Self {
             needle,
+            lookup: None,
             next_idx: 0,
             _phantom: PhantomData,
         }
     }
 
+    /// Create a searcher whose `needle` only ever matches nodes described by `lookup`.
+    pub fn with_lookup(lookup: Lookup<'a>, needle: F) -> Self {
+        Self {
+            lookup: Some(lookup),
+            ..Self::new(needle)
+        }
+    }
+
     fn idx(&self) -> usize {
         self.next_idx - 1
     }
 
     pub fn search(
         mut self,
-        nodes: &mut NodeList<'a>,
+        nodes: &mut impl NodeStore<'a>,
     ) -> Result<Option<(ActiveSearcher<'a, F>, ConfigNode<'a>)>> {
-        for node in &mut nodes[self.next_idx..] {
-            self.next_idx += 1;
+        while let Some(idx) = nodes.next_candidate(self.next_idx, self.lookup.as_ref()) {
+            self.next_idx = idx + 1;
             if (self.needle)(
-                node.as_ref().ok_or_else(|| {
+                nodes.slot(idx).ok_or_else(|| {
                     PatchingError::Internal("tried to search active patcher".into())
                 })?,
             ) {
-                return Ok(Some((ActiveSearcher(self), node.take().unwrap())));
+                return Ok(Some((ActiveSearcher(self), nodes.take(idx).unwrap())));
             }
         }
         Ok(None)
     }
 
+    /// Insert `node` before the slot at `idx`. Only unindexed lists support this, as it moves
+    /// every later node.
     pub fn insert(&mut self, idx: usize, nodes: &mut NodeList<'a>, node: ConfigNode<'a>) -> Result {
-        nodes.insert(idx, Some(node));
+        nodes.insert(idx, node);
         if idx <= self.next_idx {
             self.next_idx += 1;
         }
         Ok(())
     }
 
-    pub fn push(&mut self, nodes: &mut NodeList<'a>, node: ConfigNode<'a>) -> Result {
-        nodes.push(Some(node));
+    pub fn push(&mut self, nodes: &mut impl NodeStore<'a>, node: ConfigNode<'a>) -> Result {
+        nodes.push(node);
         Ok(())
     }
 }
@@ -67,16 +106,16 @@ where
 {
     pub fn replace(
         self,
-        nodes: &mut NodeList<'a>,
+        nodes: &mut impl NodeStore<'a>,
         node: ConfigNode<'a>,
     ) -> Result<Searcher<'a, F>> {
-        if nodes[self.0.idx()].replace(node).is_some() {
+        if nodes.replace(self.0.idx(), node).is_some() {
             internal_error("element marked active is not active")?;
         }
         Ok(self.0)
     }
 
-    pub fn delete(self, nodes: &mut NodeList<'a>) -> Result<Searcher<'a, F>> {
+    pub fn delete(self, nodes: &mut impl NodeStore<'a>) -> Result<Searcher<'a, F>> {
         if nodes.remove(self.0.idx()).is_some() {
             internal_error("element marked active is not active")?;
         }
@@ -15,6 +15,8 @@ pub struct NodePatch<'a> {
     pub index: Option<Index>,
     pub node_patches: Vec<NodePatch<'a>>,
     pub key_patches: Vec<KeyPatch<'a>>,
+    /// The number of key patches that precede this patch in its parent.
+    pub keys_before: usize,
 }
 
 impl<'a> NodePatch<'a> {
@@ -23,7 +25,10 @@ impl<'a> NodePatch<'a> {
         let mut key_patches = vec![];
         for item in node.block {
             match item {
-                parser::NodeItem::Node(node) => node_patches.push(Self::from_cst(node, false)?),
+                parser::NodeItem::Node(node) => node_patches.push(Self {
+                    keys_before: key_patches.len(),
+                    ..Self::from_cst(node, false)?
+                }),
                 parser::NodeItem::KeyVal(key) => key_patches.push(KeyPatch::from_cst(key)?),
                 parser::NodeItem::Comment(_) | parser::NodeItem::EmptyLine => {}
             }
@@ -38,6 +43,18 @@ impl<'a> NodePatch<'a> {
             index: node.index,
             node_patches,
             key_patches,
+            keys_before: 0,
         })
     }
 }
+
+/// Displays the patch's header, e.g. `@PART[foo|bar]`.
+impl<'a> std::fmt::Display for NodePatch<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}", self.operation, self.ident)?;
+        if let Some(names) = &self.target_name {
+            write!(f, "[{}]", names.join("|"))?;
+        }
+        Ok(())
+    }
+}
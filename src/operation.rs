@@ -36,3 +36,18 @@ impl<'a> Op<'a> {
         }
     }
 }
+
+impl<'a> std::fmt::Display for Op<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Insert => "",
+            Self::Copy => "+",
+            Self::CopyFrom { .. } => "#",
+            Self::Edit => "@",
+            Self::EditOrCreate => "%",
+            Self::DefaultValue => "&",
+            Self::Delete => "!",
+            Self::Rename => "|",
+        })
+    }
+}
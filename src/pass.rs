@@ -1,14 +1,30 @@
 use std::borrow::Cow;
 use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
 
 use ksp_cfg_formatter::parser;
 
-// TODO: Custom ordering.
-#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// The name of a mod, as used in pass specifiers and `:NEEDS`.
+///
+/// Like ModuleManager, identifiers are compared case-insensitively. They are ordered the way MM
+/// sorts its mod list, which uses .NET's culture-aware string comparison: case is ignored, and
+/// punctuation sorts before digits, which sort before letters.
+#[derive(Clone, Debug)]
 pub struct PassIdentifier<'a>(pub Cow<'a, str>);
 
+/// A patching pass. MM runs passes in the following order:
+///
+/// 1. `:INSERT`: all top-level insertion nodes without a pass specifier, i.e. the unpatched game
+///    database.
+/// 2. `:FIRST`.
+/// 3. `:<DEFAULT>`: patches without a pass specifier (MM's "legacy" pass).
+/// 4. `:BEFORE[mod]`, `:FOR[mod]`, and `:AFTER[mod]` for each mod in the sorted mod list.
+/// 5. `:LAST[mod]` for each mod in the sorted mod list.
+/// 6. `:FINAL`.
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Pass<'a> {
+    Insert,
     Default,
     First,
     Before(PassIdentifier<'a>),
@@ -18,6 +34,53 @@ pub enum Pass<'a> {
     Final,
 }
 
+impl<'a> PassIdentifier<'a> {
+    pub fn into_owned(self) -> PassIdentifier<'static> {
+        PassIdentifier(Cow::Owned(self.0.into_owned()))
+    }
+
+    fn sort_key(&self) -> impl Iterator<Item = (u8, char)> + '_ {
+        self.0.chars().flat_map(char::to_lowercase).map(|c| {
+            let class = if c.is_alphabetic() {
+                2
+            } else if c.is_numeric() {
+                1
+            } else {
+                0
+            };
+            (class, c)
+        })
+    }
+}
+
+impl<'a> PartialEq for PassIdentifier<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<'a> Eq for PassIdentifier<'a> {}
+
+impl<'a> Hash for PassIdentifier<'a> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for c in self.0.chars().flat_map(char::to_lowercase) {
+            c.hash(state);
+        }
+    }
+}
+
+impl<'a> PartialOrd for PassIdentifier<'a> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'a> Ord for PassIdentifier<'a> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.sort_key().cmp(other.sort_key())
+    }
+}
+
 impl<'a> From<&'a str> for PassIdentifier<'a> {
     fn from(value: &'a str) -> Self {
         Self(value.into())
@@ -44,6 +107,40 @@ impl<'a> From<parser::Pass<'a>> for Pass<'a> {
     }
 }
 
+#[derive(Clone, PartialEq, Debug, thiserror::Error)]
+#[error("`{0}` is not a valid pass")]
+pub struct ParsePassError(String);
+
+/// Parses the notation used by [`Pass`]'s `Display` implementation, e.g. `:AFTER[Foo]`.
+impl FromStr for Pass<'static> {
+    type Err = ParsePassError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParsePassError(s.to_owned());
+        let pass = s.strip_prefix(':').unwrap_or(s);
+        match pass {
+            "INSERT" => return Ok(Self::Insert),
+            "<DEFAULT>" => return Ok(Self::Default),
+            "FIRST" => return Ok(Self::First),
+            "FINAL" => return Ok(Self::Final),
+            _ => {}
+        }
+        let (kind, ident) = pass
+            .strip_suffix(']')
+            .and_then(|pass| pass.split_once('['))
+            .filter(|(_, ident)| !ident.is_empty())
+            .ok_or_else(err)?;
+        let ident = PassIdentifier::from(ident.to_owned());
+        match kind {
+            "BEFORE" => Ok(Self::Before(ident)),
+            "FOR" => Ok(Self::For(ident)),
+            "AFTER" => Ok(Self::After(ident)),
+            "LAST" => Ok(Self::Last(ident)),
+            _ => Err(err()),
+        }
+    }
+}
+
 impl<'a> std::fmt::Display for PassIdentifier<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str(&self.0)
@@ -55,6 +152,9 @@ macro_rules! pass {
     () => {
         $crate::pass::Pass::Default
     };
+    (INSERT) => {
+        $crate::pass::Pass::Insert
+    };
     (FIRST) => {
         $crate::pass::Pass::First
     };
@@ -76,15 +176,29 @@ macro_rules! pass {
 }
 
 impl<'a> Pass<'a> {
+    pub fn into_owned(self) -> Pass<'static> {
+        match self {
+            Self::Insert => Pass::Insert,
+            Self::Default => Pass::Default,
+            Self::First => Pass::First,
+            Self::Before(ident) => Pass::Before(ident.into_owned()),
+            Self::For(ident) => Pass::For(ident.into_owned()),
+            Self::After(ident) => Pass::After(ident.into_owned()),
+            Self::Last(ident) => Pass::Last(ident.into_owned()),
+            Self::Final => Pass::Final,
+        }
+    }
+
     const fn numerical_ordering(&self) -> u8 {
         match self {
-            Self::Default => 0,
+            Self::Insert => 0,
             Self::First => 1,
-            Self::Before(_) => 2,
-            Self::For(_) => 3,
-            Self::After(_) => 4,
-            Self::Last(_) => 5,
-            Self::Final => 6,
+            Self::Default => 2,
+            Self::Before(_) => 3,
+            Self::For(_) => 4,
+            Self::After(_) => 5,
+            Self::Last(_) => 6,
+            Self::Final => 7,
         }
     }
 }
@@ -113,6 +227,7 @@ impl<'a> Ord for Pass<'a> {
 impl<'a> std::fmt::Display for Pass<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            Pass::Insert => f.write_str(":INSERT"),
             Pass::Default => f.write_str(":<DEFAULT>"),
             Pass::First => f.write_str(":FIRST"),
             Pass::Before(pass) => write!(f, ":BEFORE[{pass}]"),
@@ -129,11 +244,45 @@ mod tests {
     #[test]
     fn pass_ordering() {
         assert_eq!(pass![FOR["foo"]], pass![FOR["foo"]]);
-        assert!(pass![] < pass![FIRST]);
-        assert!(pass![FIRST] < pass![BEFORE["foo"]]);
+        assert!(pass![INSERT] < pass![FIRST]);
+        assert!(pass![FIRST] < pass![]);
+        assert!(pass![] < pass![BEFORE["foo"]]);
         assert!(pass![BEFORE["foo"]] < pass![FOR["foo"]]);
         assert!(pass![FOR["foo"]] < pass![AFTER["foo"]]);
         assert!(pass![AFTER["foo"]] < pass![BEFORE["qux"]]);
-        assert!(pass![LAST["foo"]] < pass![FINAL]);
+        assert!(pass![AFTER["qux"]] < pass![LAST["foo"]]);
+        assert!(pass![LAST["foo"]] < pass![LAST["qux"]]);
+        assert!(pass![LAST["qux"]] < pass![FINAL]);
+    }
+
+    #[test]
+    fn mod_name_ordering() {
+        // Mod names are case-insensitive.
+        assert_eq!(pass![FOR["RealismOverhaul"]], pass![FOR["realismoverhaul"]]);
+        assert!(pass![AFTER["realismOverhaul"]] < pass![BEFORE["RP-1"]]);
+        assert!(pass![FOR["abc"]] < pass![FOR["ABD"]]);
+        // Punctuation sorts before digits, which sort before letters.
+        assert!(pass![FOR["_Mod"]] < pass![FOR["000_Toolbar"]]);
+        assert!(pass![FOR["000_Toolbar"]] < pass![FOR["B9"]]);
+        assert!(pass![FOR["B9"]] < pass![FOR["B9_Aerospace"]]);
+    }
+
+    #[test]
+    fn pass_round_trip() {
+        for pass in [
+            pass![INSERT],
+            pass![],
+            pass![FIRST],
+            pass![BEFORE["foo"]],
+            pass![FOR["foo"]],
+            pass![AFTER["foo"]],
+            pass![LAST["foo"]],
+            pass![FINAL],
+        ] {
+            assert_eq!(pass.to_string().parse(), Ok(pass));
+        }
+        assert_eq!("FOR[foo]".parse(), Ok(pass![FOR["foo"]]));
+        assert!(":FOR[]".parse::<super::Pass>().is_err());
+        assert!(":NEEDS[foo]".parse::<super::Pass>().is_err());
     }
 }
@@ -18,6 +18,19 @@ impl<'a> PatchSet<'a> {
     pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut PatchesInPass<'a>> {
         self.0.iter_mut()
     }
+
+    pub fn contains_pass(&self, pass: &Pass<'a>) -> bool {
+        self.0
+            .binary_search_by(|(existing, _)| existing.cmp(pass))
+            .is_ok()
+    }
+
+    /// Insert an empty pass at its sorted position, if it is not already present.
+    pub fn insert_pass(&mut self, pass: Pass<'a>) {
+        if let Err(idx) = self.0.binary_search_by(|(existing, _)| existing.cmp(&pass)) {
+            self.0.insert(idx, (pass, vec![]));
+        }
+    }
 }
 
 impl<'a> From<WorkingPatchSet<'a>> for PatchSet<'a> {
@@ -1,12 +1,14 @@
 use std::collections::{HashMap, HashSet};
-use std::rc::Rc;
+use std::sync::Arc;
 
 use ksp_cfg_formatter::parser::{Document, NodeItem};
 
 use crate::file::File;
 use crate::node_patch::NodePatch;
+use crate::operation::Op;
 use crate::pass::Pass;
 use crate::patch_set::PatchSet;
+use crate::validate::{self, Diagnostic, Severity};
 use crate::{internal_error, Result};
 
 #[derive(Debug, Default)]
@@ -14,14 +16,21 @@ pub struct RawPatches<'a> {
     pub files: Vec<File<Document<'a>>>,
 }
 
-pub type WorkingPatchSet<'a> = HashMap<Pass<'a>, HashMap<Rc<std::path::Path>, Vec<NodePatch<'a>>>>;
+pub type WorkingPatchSet<'a> = HashMap<Pass<'a>, HashMap<Arc<std::path::Path>, Vec<NodePatch<'a>>>>;
 
 impl<'a> RawPatches<'a> {
     pub fn extract(self) -> Result<PatchSet<'a>> {
+        Ok(self.extract_validated()?.0)
+    }
+
+    /// Like [`extract`](Self::extract), also returning the diagnostics of
+    /// [`validate`](Self::validate), which are found along the way.
+    pub fn extract_validated(self) -> Result<(PatchSet<'a>, Vec<Diagnostic<'a>>)> {
+        let mut diagnostics = vec![];
         let mut referenced_passes = self.extract_passes()?;
         referenced_passes.extend([Pass::Default]);
 
-        let mut patches: HashMap<Pass, HashMap<Rc<std::path::Path>, Vec<NodePatch>>> =
+        let mut patches: HashMap<Pass, HashMap<Arc<std::path::Path>, Vec<NodePatch>>> =
             referenced_passes
                 .into_iter()
                 .map(|pass| (pass, HashMap::new()))
@@ -31,12 +40,28 @@ impl<'a> RawPatches<'a> {
             for top_level_item in file.contents.statements {
                 match top_level_item {
                     NodeItem::Node(node) => {
+                        let found = validate::validate_top_level(&file.path, &node);
+                        let invalid = found
+                            .iter()
+                            .any(|diagnostic| diagnostic.severity == Severity::Error);
+                        diagnostics.extend(found);
+                        if invalid {
+                            continue;
+                        }
+                        let pass = node.pass.into();
+                        let patch = NodePatch::from_cst(node, true)?;
+                        // Insertion nodes without a pass specifier make up the initial database,
+                        // which exists before any patches run.
+                        let pass = match pass {
+                            Pass::Default if patch.operation == Op::Insert => Pass::Insert,
+                            pass => pass,
+                        };
                         patches
-                            .entry(node.pass.into())
+                            .entry(pass)
                             .or_default()
-                            .entry(Rc::clone(&file.path))
+                            .entry(Arc::clone(&file.path))
                             .or_default()
-                            .push(NodePatch::from_cst(node, true)?);
+                            .push(patch);
                     }
                     NodeItem::KeyVal(_) => internal_error("top-level keys are illegal")?,
                     NodeItem::Comment(_) | NodeItem::EmptyLine => {}
@@ -44,7 +69,7 @@ impl<'a> RawPatches<'a> {
             }
         }
 
-        Ok(patches.into())
+        Ok((patches.into(), diagnostics))
     }
 
     fn extract_passes(&self) -> Result<HashSet<Pass<'a>>> {
@@ -0,0 +1,222 @@
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use std::sync::Arc;
+
+use ksp_cfg_formatter::parser::{self, NodeItem};
+
+use crate::location::Location;
+use crate::operation::Op;
+use crate::pass::Pass;
+use crate::raw_patch::RawPatches;
+
+/// A problem with the structure of a top-level patch, detected before execution.
+///
+/// Multiple pass specifiers on the same node are rejected by the parser, and thus never reach
+/// this stage.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Diagnostic<'a> {
+    pub severity: Severity,
+    pub path: Arc<Path>,
+    /// The header of the top-level patch, e.g. `@PART[foo]:FOR[Bar]`.
+    pub patch: String,
+    /// The identifier of the offending node, which borrows from the contents of the file; see
+    /// [`Diagnostic::location`].
+    pub anchor: &'a str,
+    pub kind: DiagnosticKind<'a>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Severity {
+    /// The patch is executed, but likely does not do what its author intended.
+    Warning,
+    /// The patch is rejected and not executed, as MM does.
+    Error,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum DiagnosticKind<'a> {
+    /// A pass specifier on a node nested inside a top-level node. Only top-level nodes may
+    /// specify their pass.
+    NestedPass { node: String, pass: Pass<'a> },
+    /// A top-level insertion node with a `:FOR` pass specifier. MM quirkily inserts the node
+    /// during that pass, instead of with the rest of the initial database.
+    InsertWithFor(Pass<'a>),
+    /// A top-level insertion node with a pass specifier other than `:FOR`.
+    InsertWithPass(Pass<'a>),
+}
+
+impl<'a> RawPatches<'a> {
+    /// Check the pass specifiers of every top-level node.
+    pub fn validate(&self) -> Vec<Diagnostic<'a>> {
+        let mut diagnostics = vec![];
+        for file in &self.files {
+            for top_level_item in &file.contents.statements {
+                if let NodeItem::Node(node) = top_level_item {
+                    diagnostics.extend(validate_top_level(&file.path, node));
+                }
+            }
+        }
+        diagnostics
+    }
+}
+
+/// Validate a single top-level node. Nodes for which an [`Severity::Error`] is reported are
+/// excluded from the extracted [`PatchSet`](crate::patch_set::PatchSet).
+pub fn validate_top_level<'a>(path: &Arc<Path>, node: &parser::Node<'a>) -> Vec<Diagnostic<'a>> {
+    let mut kinds = vec![];
+    let pass = Pass::from(node.pass);
+    if operation(node) == Op::Insert {
+        match pass {
+            Pass::Default => {}
+            Pass::For(_) => kinds.push((
+                Severity::Warning,
+                node.identifier,
+                DiagnosticKind::InsertWithFor(pass),
+            )),
+            _ => kinds.push((
+                Severity::Error,
+                node.identifier,
+                DiagnosticKind::InsertWithPass(pass),
+            )),
+        }
+    }
+    for child in children(node) {
+        find_nested_passes(child, &mut kinds);
+    }
+
+    kinds
+        .into_iter()
+        .map(|(severity, anchor, kind)| Diagnostic {
+            severity,
+            path: path.clone(),
+            patch: header(node),
+            anchor,
+            kind,
+        })
+        .collect()
+}
+
+fn find_nested_passes<'a>(
+    node: &parser::Node<'a>,
+    kinds: &mut Vec<(Severity, &'a str, DiagnosticKind<'a>)>,
+) {
+    let pass = Pass::from(node.pass);
+    if pass != Pass::Default {
+        kinds.push((
+            Severity::Error,
+            node.identifier,
+            DiagnosticKind::NestedPass {
+                node: header(node),
+                pass,
+            },
+        ));
+    }
+    for child in children(node) {
+        find_nested_passes(child, kinds);
+    }
+}
+
+fn children<'b, 'a>(node: &'b parser::Node<'a>) -> impl Iterator<Item = &'b parser::Node<'a>> {
+    node.block.iter().filter_map(|item| match item {
+        NodeItem::Node(child) => Some(child),
+        _ => None,
+    })
+}
+
+/// The operation of a node, e.g. [`Op::Edit`] for `@PART`.
+pub fn operation<'a>(node: &parser::Node<'a>) -> Op<'a> {
+    Op::new(
+        node.operator.clone(),
+        node.path.clone().map(|path| (path, node.identifier)),
+    )
+}
+
+pub(crate) fn header(node: &parser::Node) -> String {
+    let mut header = format!("{}{}", operation(node), node.identifier);
+    if let Some(names) = &node.name {
+        header += &format!("[{}]", names.join("|"));
+    }
+    let pass = Pass::from(node.pass);
+    if pass != Pass::Default {
+        header += &pass.to_string();
+    }
+    header
+}
+
+impl<'a> Diagnostic<'a> {
+    /// The position of the offending node within `source`, the contents of its file, or `None` if
+    /// the anchor does not point into `source`.
+    pub fn location(&self, source: &str) -> Option<Location> {
+        Location::of(source, self.anchor)
+    }
+}
+
+impl<'a> Display for Diagnostic<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(
+            f,
+            "{severity}: {} in {}: {}",
+            self.patch,
+            self.path.to_string_lossy(),
+            self.kind
+        )
+    }
+}
+
+impl<'a> Display for DiagnosticKind<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NestedPass { node, pass } => {
+                write!(f, "nested node `{node}` cannot specify the pass {pass}")
+            }
+            Self::InsertWithFor(pass) => write!(
+                f,
+                "insertion node specifies the pass {pass}, and will be inserted during it"
+            ),
+            Self::InsertWithPass(pass) => {
+                write!(f, "insertion node cannot specify the pass {pass}")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::File;
+
+    #[test]
+    fn parser_rejects_multiple_passes() {
+        for header in ["@PART[a]:FOR[A]:FOR[B]", "@PART[a]:FIRST:FINAL"] {
+            let cfg = format!("{header}\n{{\n}}\n");
+            assert!(
+                ksp_cfg_formatter::parse_to_ast(&cfg).is_err(),
+                "`{header}` was parsed"
+            );
+        }
+    }
+
+    #[test]
+    fn extraction_reports_validation() {
+        let cfg = "PART:FIRST\n{\n}\nPART\n{\n\tMODULE:FINAL\n\t{\n\t}\n}\nPART:FOR[A]\n{\n}\n";
+        let raw = || RawPatches {
+            files: vec![File::new(
+                Arc::from(Path::new("A/a.cfg")),
+                ksp_cfg_formatter::parse_to_ast(cfg).unwrap(),
+            )],
+        };
+        let diagnostics = raw().validate();
+        assert_eq!(
+            diagnostics
+                .iter()
+                .map(|diagnostic| diagnostic.severity)
+                .collect::<Vec<_>>(),
+            [Severity::Error, Severity::Error, Severity::Warning]
+        );
+        assert_eq!(raw().extract_validated().unwrap().1, diagnostics);
+    }
+}
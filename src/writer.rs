@@ -0,0 +1,327 @@
+use std::fmt::Write;
+use std::path::PathBuf;
+
+use crate::config_node::{ConfigKey, ConfigNode};
+use crate::database::Database;
+use crate::diff::parent_url;
+
+/// The indentation of nested lines.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Indent {
+    /// One tab per level, as KSP writes its own files.
+    #[default]
+    Tabs,
+    Spaces(usize),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum WriteError {
+    #[error("{what} `{text}` cannot be written to a cfg file: {reason}")]
+    Unrepresentable {
+        what: &'static str,
+        text: String,
+        reason: &'static str,
+    },
+    #[error(transparent)]
+    Fmt(#[from] std::fmt::Error),
+    #[error("`{0}` is not a path inside of GameData")]
+    InvalidPath(PathBuf),
+    #[error("failed to write `{path}`: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+/// Writes nodes as cfg files that KSP and this crate read back into the same nodes.
+///
+/// Keys and child nodes are interleaved according to [`ConfigNode::keys_before`]. The cfg format
+/// has no escapes, so text that would be read back differently, such as a value containing `{` or
+/// `//`, is rejected with [`WriteError::Unrepresentable`] instead of being written.
+#[derive(Clone, Debug, Default)]
+pub struct Writer {
+    pub indent: Indent,
+}
+
+impl Writer {
+    pub fn new(indent: Indent) -> Self {
+        Self { indent }
+    }
+
+    /// Write `nodes` as the contents of a single file.
+    pub fn write_nodes<'b, 'a: 'b>(
+        &self,
+        out: &mut impl Write,
+        nodes: impl IntoIterator<Item = &'b ConfigNode<'a>>,
+    ) -> Result<(), WriteError> {
+        for node in nodes {
+            self.write_node(out, node, 0)?;
+        }
+        Ok(())
+    }
+
+    pub fn write_node(
+        &self,
+        out: &mut impl Write,
+        node: &ConfigNode,
+        depth: usize,
+    ) -> Result<(), WriteError> {
+        check_node_ident(&node.ident)?;
+        self.write_indent(out, depth)?;
+        writeln!(out, "{}", node.ident)?;
+        self.write_indent(out, depth)?;
+        writeln!(out, "{{")?;
+
+        let mut keys = node.keys.iter();
+        let mut written = 0;
+        for child in &node.nodes {
+            // Patches may delete keys, so a child can claim more preceding keys than are left.
+            for key in keys
+                .by_ref()
+                .take(child.keys_before.saturating_sub(written))
+            {
+                self.write_key(out, key, depth + 1)?;
+                written += 1;
+            }
+            self.write_node(out, child, depth + 1)?;
+        }
+        for key in keys {
+            self.write_key(out, key, depth + 1)?;
+        }
+
+        self.write_indent(out, depth)?;
+        writeln!(out, "}}")?;
+        Ok(())
+    }
+
+    pub fn write_key(
+        &self,
+        out: &mut impl Write,
+        key: &ConfigKey,
+        depth: usize,
+    ) -> Result<(), WriteError> {
+        check_key(key)?;
+        self.write_indent(out, depth)?;
+        if key.value.is_empty() {
+            writeln!(out, "{} =", key.ident)?;
+        } else {
+            writeln!(out, "{} = {}", key.ident, key.value)?;
+        }
+        Ok(())
+    }
+
+    /// Write `database` in the format of `ModuleManager.ConfigCache`, where each top-level node is
+    /// wrapped in a `UrlConfig` node recording its `parentUrl`. MM's `patchedNodeCount` is not
+    /// tracked, and thus omitted.
+    pub fn write_config_cache(
+        &self,
+        out: &mut impl Write,
+        database: &Database,
+    ) -> Result<(), WriteError> {
+        for node in database.nodes() {
+            let url = node.file_path.as_deref().map(parent_url);
+            writeln!(out, "UrlConfig")?;
+            writeln!(out, "{{")?;
+            self.write_key(
+                out,
+                &ConfigKey::new("parentUrl", url.unwrap_or_default()),
+                1,
+            )?;
+            self.write_node(out, node, 1)?;
+            writeln!(out, "}}")?;
+        }
+        Ok(())
+    }
+
+    /// Write a single node to a string.
+    pub fn node_to_string(&self, node: &ConfigNode) -> Result<String, WriteError> {
+        let mut out = String::new();
+        self.write_node(&mut out, node, 0)?;
+        Ok(out)
+    }
+
+    fn write_indent(&self, out: &mut impl Write, depth: usize) -> std::fmt::Result {
+        match self.indent {
+            Indent::Tabs => write!(out, "{:\t<1$}", "", depth),
+            Indent::Spaces(size) => write!(out, "{:1$}", "", size * depth),
+        }
+    }
+}
+
+/// Operators that turn a line into a patch when they start a key or node type.
+const OPERATORS: &[char] = &['@', '%', '&', '+', '$', '-', '!', '|', '#', '*'];
+/// Assignment operators, such as `*=`, that a key may not end with.
+const ASSIGNMENT_OPERATORS: &[char] = &['*', '/', '+', '-', '!', '^'];
+
+fn unrepresentable(what: &'static str, text: &str, reason: &'static str) -> WriteError {
+    WriteError::Unrepresentable {
+        what,
+        text: text.to_owned(),
+        reason,
+    }
+}
+
+/// Reject text that does not survive being read back as part of a line.
+fn check_text(what: &'static str, text: &str) -> Result<(), WriteError> {
+    let reason = if text.contains(['{', '}']) {
+        "braces delimit nodes"
+    } else if text.contains("//") {
+        "`//` starts a comment"
+    } else if text.contains(['\n', '\r']) {
+        "it spans multiple lines"
+    } else if text.trim() != text {
+        "surrounding whitespace is trimmed"
+    } else {
+        return Ok(());
+    };
+    Err(unrepresentable(what, text, reason))
+}
+
+fn check_ident(what: &'static str, ident: &str) -> Result<(), WriteError> {
+    check_text(what, ident)?;
+    let reason = if ident.is_empty() {
+        "it is empty"
+    } else if ident.starts_with(OPERATORS) {
+        "it starts with a patch operator"
+    } else if ident.contains(['=', ':', ',', '[', ']']) {
+        "it contains patch syntax"
+    } else {
+        return Ok(());
+    };
+    Err(unrepresentable(what, ident, reason))
+}
+
+fn check_node_ident(ident: &str) -> Result<(), WriteError> {
+    check_ident("node type", ident)?;
+    if ident.contains(char::is_whitespace) {
+        return Err(unrepresentable(
+            "node type",
+            ident,
+            "it contains whitespace",
+        ));
+    }
+    Ok(())
+}
+
+fn check_key(key: &ConfigKey) -> Result<(), WriteError> {
+    check_ident("key", &key.ident)?;
+    if key.ident.ends_with(ASSIGNMENT_OPERATORS) {
+        return Err(unrepresentable(
+            "key",
+            &key.ident,
+            "it ends with an assignment operator",
+        ));
+    }
+    check_text("value", &key.value)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::diff::DatabaseDiff;
+    use crate::module_manager::patcher::evaluate_node_as_pure_data;
+    use crate::node_patch::NodePatch;
+
+    /// The nodes of a cfg file, read the way GameData files are.
+    fn read_back(cfg: &str) -> Vec<ConfigNode<'_>> {
+        let path: Arc<Path> = Arc::from(Path::new("Test/test.cfg"));
+        ksp_cfg_formatter::parse_to_ast(cfg)
+            .unwrap()
+            .statements
+            .into_iter()
+            .filter_map(|item| match item {
+                ksp_cfg_formatter::parser::NodeItem::Node(node) => Some(node),
+                _ => None,
+            })
+            .map(|node| {
+                evaluate_node_as_pure_data(path.clone(), &NodePatch::from_cst(node, true).unwrap())
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    const CFG: &str = "PART
+{
+	name = a
+	MODULE
+	{
+		name = ModuleEngines
+		PROPELLANT
+		{
+			ratio =
+		}
+	}
+	mass = 1.5
+	RESOURCE
+	{
+		amount = 10
+	}
+	tags = a b c
+}
+";
+
+    #[test]
+    fn round_trip() {
+        let nodes = read_back(CFG);
+        let mut written = String::new();
+        Writer::default().write_nodes(&mut written, &nodes).unwrap();
+        assert_eq!(written, CFG);
+        assert_eq!(read_back(&written), nodes);
+
+        let spaces = Writer::new(Indent::Spaces(2))
+            .node_to_string(&nodes[0])
+            .unwrap();
+        assert!(spaces.contains("\n  MODULE\n  {\n    name = ModuleEngines\n"));
+        assert_eq!(read_back(&spaces), nodes);
+    }
+
+    #[test]
+    fn config_cache_round_trip() {
+        let mut database = Database::default();
+        for mut node in read_back(CFG) {
+            node.file_path = Some(Arc::from(Path::new("Mod/parts.cfg")));
+            database.insert(node).unwrap();
+        }
+        let mut cache = String::new();
+        Writer::default()
+            .write_config_cache(&mut cache, &database)
+            .unwrap();
+        assert!(cache.starts_with("UrlConfig\n{\n\tparentUrl = Mod/parts\n\tPART\n"));
+
+        let read =
+            Database::from_config_cache(ksp_cfg_formatter::parse_to_ast(&cache).unwrap()).unwrap();
+        assert!(DatabaseDiff::new(&database, &read).is_empty());
+    }
+
+    #[test]
+    fn rejects_unrepresentable_text() {
+        let node = |key: ConfigKey<'static>| ConfigNode {
+            ident: "PART".into(),
+            keys: vec![key],
+            ..Default::default()
+        };
+        for key in [
+            ConfigKey::new("name", "a { b"),
+            ConfigKey::new("name", "http://example.com"),
+            ConfigKey::new("name", "a\nb"),
+            ConfigKey::new("name", " a"),
+            ConfigKey::new("@name", "a"),
+            ConfigKey::new("mass*", "2"),
+            ConfigKey::new("", "a"),
+        ] {
+            assert!(
+                matches!(
+                    Writer::default().node_to_string(&node(key.clone())),
+                    Err(WriteError::Unrepresentable { .. })
+                ),
+                "{key:?} was written"
+            );
+        }
+        assert!(Writer::default()
+            .node_to_string(&node(ConfigKey::new("description", "a = b, c: [d]")))
+            .is_ok());
+    }
+}
@@ -0,0 +1,363 @@
+//! Property tests of the patch engine on random databases and patches.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use module_manager_rs::config_node::{ConfigKey, ConfigNode, NodeList};
+use module_manager_rs::database::Database;
+use module_manager_rs::file::File;
+use module_manager_rs::game_data::GameData;
+use module_manager_rs::key_patch::KeyPatch;
+use module_manager_rs::module_manager::patcher::{evaluate_node_as_pure_data, Patcher};
+use module_manager_rs::node_patch::NodePatch;
+use module_manager_rs::operation::Op;
+use module_manager_rs::writer::Writer;
+use module_manager_rs::PatchingError;
+use proptest::prelude::*;
+
+/// A small vocabulary, so that patches regularly match nodes.
+const IDENTS: &[&str] = &["PART", "MODULE", "RESOURCE"];
+const KEYS: &[&str] = &["name", "mass", "tag"];
+const VALUES: &[&str] = &["a", "b", "c", "1.5"];
+/// Values of data keys, including an operator character that is only special at the start of a
+/// key.
+const DATA_VALUES: &[&str] = &["a", "b", "c", "1.5", "a&b"];
+/// Pass specifiers of top-level patches. The fuzzed file is in the `Fuzz` mod.
+const PASSES: &[&str] = &[
+    "",
+    ":FIRST",
+    ":FOR[Fuzz]",
+    ":AFTER[Fuzz]",
+    ":LAST[Fuzz]",
+    ":FINAL",
+];
+
+/// An owned description of a node, from which both data nodes and patches are built.
+#[derive(Clone, Debug)]
+struct NodeSpec {
+    operation: OpSpec,
+    ident: &'static str,
+    names: Option<Vec<&'static str>>,
+    keys: Vec<(&'static str, &'static str)>,
+    nodes: Vec<NodeSpec>,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum OpSpec {
+    Insert,
+    Copy,
+    Edit,
+    EditOrCreate,
+    DefaultValue,
+    Delete,
+    Rename,
+}
+
+impl From<OpSpec> for Op<'static> {
+    fn from(value: OpSpec) -> Self {
+        match value {
+            OpSpec::Insert => Op::Insert,
+            OpSpec::Copy => Op::Copy,
+            OpSpec::Edit => Op::Edit,
+            OpSpec::EditOrCreate => Op::EditOrCreate,
+            OpSpec::DefaultValue => Op::DefaultValue,
+            OpSpec::Delete => Op::Delete,
+            OpSpec::Rename => Op::Rename,
+        }
+    }
+}
+
+fn op() -> impl Strategy<Value = OpSpec> {
+    prop_oneof![
+        Just(OpSpec::Insert),
+        Just(OpSpec::Copy),
+        Just(OpSpec::Edit),
+        Just(OpSpec::EditOrCreate),
+        Just(OpSpec::DefaultValue),
+        Just(OpSpec::Delete),
+        Just(OpSpec::Rename),
+    ]
+}
+
+fn keys() -> impl Strategy<Value = Vec<(&'static str, &'static str)>> {
+    prop::collection::vec(
+        (
+            prop::sample::select(KEYS),
+            prop::sample::select(DATA_VALUES),
+        ),
+        0..4,
+    )
+}
+
+/// Nodes that only contain data.
+fn data_node() -> impl Strategy<Value = NodeSpec> {
+    let leaf = (prop::sample::select(IDENTS), keys()).prop_map(|(ident, keys)| NodeSpec {
+        operation: OpSpec::Insert,
+        ident,
+        names: None,
+        keys,
+        nodes: vec![],
+    });
+    leaf.prop_recursive(3, 16, 3, |inner| {
+        (
+            prop::sample::select(IDENTS),
+            keys(),
+            prop::collection::vec(inner, 0..3),
+        )
+            .prop_map(|(ident, keys, nodes)| NodeSpec {
+                operation: OpSpec::Insert,
+                ident,
+                names: None,
+                keys,
+                nodes,
+            })
+    })
+}
+
+/// Patches with arbitrary operations and name filters.
+fn patch_node() -> impl Strategy<Value = NodeSpec> {
+    let names = prop::option::of(prop::collection::vec(prop::sample::select(VALUES), 1..3));
+    let leaf = (op(), prop::sample::select(IDENTS), names.clone(), keys()).prop_map(
+        |(operation, ident, names, keys)| NodeSpec {
+            operation,
+            ident,
+            names,
+            keys,
+            nodes: vec![],
+        },
+    );
+    leaf.prop_recursive(3, 16, 3, move |inner| {
+        (
+            op(),
+            prop::sample::select(IDENTS),
+            names.clone(),
+            keys(),
+            prop::collection::vec(inner, 0..3),
+        )
+            .prop_map(|(operation, ident, names, keys, nodes)| NodeSpec {
+                operation,
+                ident,
+                names,
+                keys,
+                nodes,
+            })
+    })
+}
+
+/// A patch that only deletes nodes, or edits them by deleting their children. Which nodes survive
+/// depends only on their type and name and those of their ancestors, so applying these patches a
+/// second time changes nothing.
+#[derive(Clone, Debug)]
+struct RemovalSpec {
+    operation: RemovalOp,
+    ident: &'static str,
+    names: Option<Vec<&'static str>>,
+    nodes: Vec<RemovalSpec>,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum RemovalOp {
+    Edit,
+    Delete,
+}
+
+/// Top-level removal patches, with a pass specifier.
+fn idempotent_patch() -> impl Strategy<Value = (RemovalSpec, &'static str)> {
+    let names = prop::option::of(prop::collection::vec(prop::sample::select(VALUES), 1..3));
+    let operation = prop_oneof![Just(RemovalOp::Edit), Just(RemovalOp::Delete)];
+    let leaf = (
+        operation.clone(),
+        prop::sample::select(IDENTS),
+        names.clone(),
+    )
+        .prop_map(|(operation, ident, names)| RemovalSpec {
+            operation,
+            ident,
+            names,
+            nodes: vec![],
+        });
+    let patch = leaf.prop_recursive(3, 16, 3, move |inner| {
+        (
+            operation.clone(),
+            prop::sample::select(IDENTS),
+            names.clone(),
+            prop::collection::vec(inner, 0..3),
+        )
+            .prop_map(|(operation, ident, names, nodes)| RemovalSpec {
+                operation,
+                ident,
+                names,
+                nodes: match operation {
+                    RemovalOp::Delete => vec![],
+                    RemovalOp::Edit => nodes,
+                },
+            })
+    });
+    (patch, prop::sample::select(PASSES))
+}
+
+impl RemovalSpec {
+    /// Write this patch as cfg, with `pass` appended to its header.
+    fn write(&self, out: &mut String, pass: &str) {
+        out.push(match self.operation {
+            RemovalOp::Edit => '@',
+            RemovalOp::Delete => '!',
+        });
+        out.push_str(self.ident);
+        if let Some(names) = &self.names {
+            out.push_str(&format!("[{}]", names.join("|")));
+        }
+        out.push_str(pass);
+        out.push_str("\n{\n");
+        for node in &self.nodes {
+            node.write(out, "");
+        }
+        out.push_str("}\n");
+    }
+}
+
+impl NodeSpec {
+    fn to_patch(&self, is_top_level: bool) -> NodePatch<'static> {
+        NodePatch {
+            is_top_level,
+            operation: self.operation.into(),
+            ident: self.ident,
+            target_name: self.names.clone(),
+            has: vec![],
+            needs: vec![],
+            index: None,
+            node_patches: self.nodes.iter().map(|node| node.to_patch(false)).collect(),
+            key_patches: self
+                .keys
+                .iter()
+                .map(|&(ident, value)| KeyPatch {
+                    operation: Op::Insert,
+                    ident,
+                    needs: vec![],
+                    edit: None,
+                    index: None,
+                    array_index: None,
+                    value,
+                })
+                .collect(),
+            keys_before: 0,
+        }
+    }
+
+    fn to_node(&self) -> ConfigNode<'static> {
+        let mut node = ConfigNode::new(self.ident);
+        node.nodes = self.nodes.iter().map(NodeSpec::to_node).collect();
+        node.keys = self
+            .keys
+            .iter()
+            .map(|&(ident, value)| ConfigKey::new(ident, value))
+            .collect();
+        node
+    }
+}
+
+/// `node` as a top-level node of the fuzzed file.
+fn in_file(mut node: ConfigNode<'static>) -> ConfigNode<'static> {
+    node.file_path = Some(path());
+    node
+}
+
+fn path() -> Arc<Path> {
+    Arc::from(Path::new("Fuzz/fuzz.cfg"))
+}
+
+fn database(nodes: &[NodeSpec]) -> Database<'static> {
+    nodes.iter().map(|node| in_file(node.to_node())).collect()
+}
+
+/// Every slot of the list and its descendants holds a node, i.e. no node was left taken out by a
+/// searcher.
+fn assert_no_active_slots(nodes: &NodeList) {
+    assert_eq!(
+        nodes.len(),
+        nodes.iter().count(),
+        "node list has active slots"
+    );
+    for node in nodes {
+        assert_no_active_slots(&node.nodes);
+    }
+}
+
+proptest! {
+    #[test]
+    fn patches_leave_consistent_database(
+        nodes in prop::collection::vec(data_node(), 0..8),
+        patches in prop::collection::vec(patch_node(), 1..6),
+    ) {
+        let mut database = database(&nodes);
+        for patch in &patches {
+            let patch = patch.to_patch(true);
+            match Patcher::new(&mut database, path(), &patch).evaluate() {
+                Ok(()) | Err(PatchingError::Runtime { .. }) => {}
+                Err(e) => prop_assert!(false, "unexpected error for patch {patch}: {e}"),
+            }
+            assert_no_active_slots(database.nodes());
+        }
+        prop_assert_eq!(
+            database.nodes().iter().filter(|node| node.file_path.is_none()).count(),
+            0,
+            "top-level nodes without a file"
+        );
+    }
+
+    #[test]
+    fn patches_are_idempotent(
+        nodes in prop::collection::vec(data_node(), 0..8),
+        patches in prop::collection::vec(idempotent_patch(), 1..6),
+    ) {
+        let mut patch_cfg = String::new();
+        for (patch, pass) in &patches {
+            patch.write(&mut patch_cfg, pass);
+        }
+        // Parse, extract and patch a file with the data followed by the patches.
+        let run = |data: &Database| {
+            let mut cfg = String::new();
+            Writer::default().write_nodes(&mut cfg, data.nodes()).unwrap();
+            cfg += &patch_cfg;
+            GameData::from_files(vec![File::new(path(), cfg)])
+                .owned_database(std::iter::empty())
+                .unwrap()
+        };
+
+        let once = run(&database(&nodes));
+        let twice = run(&once);
+        prop_assert_eq!(&once, &twice, "patches:\n{}", patch_cfg);
+    }
+
+    #[test]
+    fn pure_data_round_trips(node in data_node()) {
+        let patch = node.to_patch(true);
+        let data = evaluate_node_as_pure_data(path(), &patch).unwrap();
+        prop_assert_eq!(&data, &node.to_node());
+
+        // Writing the node and reading it back yields the same node.
+        let cfg = Writer::default().node_to_string(&data).unwrap();
+        let read = ksp_cfg_formatter::parse_to_ast(&cfg).unwrap().statements.remove(0);
+        let ksp_cfg_formatter::parser::NodeItem::Node(read) = read else {
+            panic!("expected a node in {cfg}");
+        };
+        let read = evaluate_node_as_pure_data(path(), &NodePatch::from_cst(read, true).unwrap());
+        prop_assert_eq!(read.as_ref(), Ok(&data));
+
+        // Inserting the node into a database yields the same node.
+        let mut database = Database::default();
+        Patcher::new(&mut database, path(), &patch).evaluate().unwrap();
+        let inserted = database.nodes().iter().next().unwrap();
+        prop_assert_eq!(inserted, &in_file(data.clone()));
+
+        // So does editing it with an empty patch.
+        let edit = NodePatch {
+            operation: Op::Edit,
+            key_patches: vec![],
+            node_patches: vec![],
+            ..patch
+        };
+        Patcher::new(&mut database, path(), &edit).evaluate().unwrap();
+        prop_assert_eq!(database.nodes().iter().next().unwrap(), &in_file(data));
+    }
+}
@@ -1,15 +1,30 @@
+//! Runs each snippet in `tests/snippets`. A snippet consists of the following blocks:
+//!
+//! - `PATCH`: the contents of a file at the snippet's own path.
+//! - `FILE`: the contents of a virtual file at its `path` key, relative to GameData. The
+//!   directories of all files are created, and their top-level directories become mods.
+//! - `FOLDERS`: additional directories, given by `folder` keys.
+//! - `DLLS`: additional mods, given by `dll` keys.
+//! - `EXPECT`: the expected database. Nodes from files other than the snippet are wrapped in a
+//!   `URL_CONFIG` node with a `parentUrl` key.
+//! - `EXPECT_ERROR` and `EXPECT_WARNING`: see [`Report`].
+
 use std::ffi::OsStr;
-use std::path::Path;
-use std::rc::Rc;
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use anyhow::Context;
+use anyhow::{ensure, Context};
 use itertools::Itertools;
 use ksp_cfg_formatter::parser::{Document, Node, NodeItem};
 use module_manager_rs::database::Database;
 use module_manager_rs::file::File;
+use module_manager_rs::game_data;
 use module_manager_rs::module_manager::{patcher, ModuleManager};
 use module_manager_rs::node_patch::NodePatch;
 use module_manager_rs::raw_patch::RawPatches;
+use module_manager_rs::validate::Severity;
+use module_manager_rs::PatchingError;
 use walkdir::WalkDir;
 
 const SNIPPETS_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/snippets");
@@ -25,9 +40,107 @@ fn find_node_by_name<'a>(document: &mut Document<'a>, name: &str) -> Option<Node
     })
 }
 
+fn find_nodes_by_name<'a>(document: &mut Document<'a>, name: &str) -> Vec<Node<'a>> {
+    std::iter::from_fn(|| find_node_by_name(document, name)).collect()
+}
+
+fn key<'a>(node: &Node<'a>, key: &str) -> Option<&'a str> {
+    node.block.iter().find_map(|item| match item {
+        NodeItem::KeyVal(item) if item.key == key => Some(item.val),
+        _ => None,
+    })
+}
+
+/// An error or warning reported while extracting or executing a snippet, or the expectation of
+/// one declared by an `EXPECT_ERROR` or `EXPECT_WARNING` block:
+///
+/// ```cfg
+/// EXPECT_ERROR
+/// {
+///     kind = NestedPass   // the variant of `RuntimeError` or `DiagnosticKind`
+///     file = snippet.cfg  // optional: the path of the file, relative to `tests/snippets`
+///     patch = @Node       // optional: the header of the top-level patch
+///     line = 12           // optional: the line of the offending node within the snippet
+/// }
+/// ```
+///
+/// Runtime errors are not located within their file, so only validation diagnostics can be
+/// expected at a `line`.
+#[derive(Debug)]
+struct Report {
+    severity: Severity,
+    kind: String,
+    file: Option<String>,
+    patch: Option<String>,
+    line: Option<usize>,
+    message: String,
+}
+
+impl Report {
+    fn expected(severity: Severity, node: &Node) -> anyhow::Result<Self> {
+        let kind = key(node, "kind").context("expected report without a `kind`")?;
+        let line = key(node, "line")
+            .map(|line| line.parse::<usize>().context("invalid `line`"))
+            .transpose()?;
+        let mut message = match severity {
+            Severity::Warning => format!("warning `{kind}`"),
+            Severity::Error => format!("error `{kind}`"),
+        };
+        if let Some(line) = line {
+            message += &format!(" at line {line}");
+        }
+        Ok(Self {
+            severity,
+            kind: kind.to_owned(),
+            file: key(node, "file").map(ToOwned::to_owned),
+            patch: key(node, "patch").map(ToOwned::to_owned),
+            line,
+            message,
+        })
+    }
+
+    fn from_error(error: &PatchingError) -> Self {
+        let (kind, file) = match error {
+            PatchingError::Runtime { path, kind } => (
+                variant_name(kind),
+                Some(path.to_string_lossy().into_owned()),
+            ),
+            error => (variant_name(error), None),
+        };
+        Self {
+            severity: Severity::Error,
+            kind,
+            file,
+            patch: None,
+            line: None,
+            message: error.to_string(),
+        }
+    }
+
+    /// Whether `self`, an expected report, is satisfied by `actual`.
+    fn matches(&self, actual: &Report) -> bool {
+        self.severity == actual.severity
+            && self.kind == actual.kind
+            && (self.file.is_none() || self.file == actual.file)
+            && (self.patch.is_none() || self.patch == actual.patch)
+            && (self.line.is_none() || self.line == actual.line)
+    }
+}
+
+/// The name of an enum variant, e.g. `NestedPass` for `NestedPass { .. }`.
+fn variant_name(value: &impl Debug) -> String {
+    let debug = format!("{value:?}");
+    debug
+        .split(|c: char| !c.is_alphanumeric() && c != '_')
+        .next()
+        .unwrap_or_default()
+        .to_owned()
+}
+
 fn run_snippet(path: &Path) -> anyhow::Result<()> {
     let file = std::fs::read_to_string(path)?;
     let mut cfg = ksp_cfg_formatter::parse_to_ast(&file)?;
+    let relative_path = Arc::from(path.strip_prefix(SNIPPETS_PATH)?);
 
     let dll_names = find_node_by_name(&mut cfg, "DLLS")
         .iter()
@@ -39,48 +152,126 @@ fn run_snippet(path: &Path) -> anyhow::Result<()> {
         .map(ToOwned::to_owned)
         .collect_vec();
 
-    let patch = RawPatches {
-        files: vec![File {
-            path: Rc::from(path),
-            contents: Document {
-                statements: find_node_by_name(&mut cfg, "PATCH")
-                    .context("snippet does not specify a `PATCH`")?
-                    .block,
-            },
-        }],
-    };
+    let mut expected_reports = vec![];
+    for node in find_nodes_by_name(&mut cfg, "EXPECT_ERROR") {
+        expected_reports.push(Report::expected(Severity::Error, &node)?);
+    }
+    for node in find_nodes_by_name(&mut cfg, "EXPECT_WARNING") {
+        expected_reports.push(Report::expected(Severity::Warning, &node)?);
+    }
 
-    let expect = Database(
-        find_node_by_name(&mut cfg, "EXPECT")
-            .context("snippet does not specify an `EXPECT`")?
+    // The snippet's `PATCH` is a file at the snippet's own path, and each `FILE` is a virtual file
+    // at its `path`, relative to GameData.
+    let mut files = vec![];
+    if let Some(patch) = find_node_by_name(&mut cfg, "PATCH") {
+        files.push(File::new(
+            Arc::clone(&relative_path),
+            Document {
+                statements: patch.block,
+            },
+        ));
+    }
+    for file in find_nodes_by_name(&mut cfg, "FILE") {
+        let path: Arc<Path> = Arc::from(Path::new(
+            key(&file, "path").context("`FILE` without a `path`")?,
+        ));
+        let statements = file
             .block
             .into_iter()
-            .filter_map(|item| match item {
+            .filter(|item| !matches!(item, NodeItem::KeyVal(key) if key.key == "path"))
+            .collect();
+        files.push(File::new(path, Document { statements }));
+    }
+    ensure!(
+        !files.is_empty(),
+        "snippet does not specify a `PATCH` or `FILE`"
+    );
+    let mut directories = game_data::directories(files.iter().map(|file| &*file.path));
+    for folders in find_nodes_by_name(&mut cfg, "FOLDERS") {
+        directories.extend(folders.block.iter().filter_map(|item| match item {
+            NodeItem::KeyVal(key) if key.key == "folder" => Some(PathBuf::from(key.val)),
+            _ => None,
+        }));
+    }
+    let patch = RawPatches { files };
+
+    let expect = find_node_by_name(&mut cfg, "EXPECT")
+        .map(|expect| -> module_manager_rs::Result<_> {
+            let mut database = vec![];
+            for node in expect.block.into_iter().filter_map(|item| match item {
                 NodeItem::Node(node) => Some(node),
                 _ => None,
-            })
-            .map(|node| -> module_manager_rs::Result<_> {
+            }) {
+                // Nodes of other files are wrapped in a `URL_CONFIG` with their `parentUrl`, the
+                // way the database is displayed.
+                if node.identifier == "URL_CONFIG" {
+                    let url_config = Database::from_config_cache(Document {
+                        statements: vec![NodeItem::Node(node)],
+                    })?;
+                    database.extend(url_config.nodes().iter().cloned());
+                    continue;
+                }
                 let mut data = patcher::evaluate_node_as_pure_data(
-                    Rc::from(path),
+                    Arc::clone(&relative_path),
                     &NodePatch::from_cst(node, true)?,
                 )?;
-                data.file_path = Some(Rc::from(path));
-                Ok(Some(data))
-            })
-            .collect::<Result<Vec<_>, _>>()?,
-    );
-
-    let mm = ModuleManager::new(patch, dll_names.iter().map(AsRef::as_ref))
-        .context("patch extraction failed")
-        .unwrap();
+                data.file_path = Some(Arc::clone(&relative_path));
+                database.push(data);
+            }
+            Ok(database.into_iter().collect::<Database>())
+        })
+        .transpose()?;
 
-    let evaluated = mm.execute().context("patch execution failed").unwrap();
+    let mut reports = vec![];
+    let evaluated = ModuleManager::new(
+        patch,
+        dll_names.iter().map(AsRef::as_ref),
+        directories.iter().map(AsRef::as_ref),
+    )
+    .and_then(|mm| {
+        reports.extend(mm.diagnostics().iter().map(|diagnostic| Report {
+            severity: diagnostic.severity,
+            kind: variant_name(&diagnostic.kind),
+            file: Some(diagnostic.path.to_string_lossy().into_owned()),
+            patch: Some(diagnostic.patch.clone()),
+            line: diagnostic.location(&file).map(|location| location.line),
+            message: diagnostic.to_string(),
+        }));
+        mm.execute()
+    })
+    .map_err(|e| reports.push(Report::from_error(&e)));
 
-    assert!(
-        expect == evaluated,
-        "expected output:\n{expect}\ngot output:\n{evaluated}"
+    let mut failures = vec![];
+    for expected in &expected_reports {
+        match reports.iter().position(|actual| expected.matches(actual)) {
+            Some(idx) => {
+                reports.remove(idx);
+            }
+            None => failures.push(format!("expected {} was not reported", expected.message)),
+        }
+    }
+    failures.extend(
+        reports
+            .iter()
+            .map(|unexpected| format!("unexpected {}", unexpected.message)),
     );
 
+    match (expect, evaluated) {
+        (Some(expect), Ok(evaluated)) if expect != evaluated => failures.push(format!(
+            "expected output:\n{expect}\ngot output:\n{evaluated}"
+        )),
+        (None, Ok(_)) => failures.push("snippet does not specify an `EXPECT`".to_owned()),
+        _ => {}
+    }
+
+    if let Some(NodeItem::Node(node)) = cfg
+        .statements
+        .iter()
+        .find(|item| matches!(item, NodeItem::Node(_)))
+    {
+        failures.push(format!("unknown block `{}`", node.identifier));
+    }
+    ensure!(failures.is_empty(), failures.join("\n"));
     Ok(())
 }
 
@@ -91,25 +282,35 @@ fn main() -> anyhow::Result<()> {
     println!();
     let mut passed = 0;
     let mut failed = 0;
-    for snippet in WalkDir::new(SNIPPETS_PATH) {
+    for snippet in WalkDir::new(SNIPPETS_PATH).sort_by_file_name() {
         let snippet = snippet?.into_path();
         if !snippet.is_file() || snippet.extension() != Some(OsStr::new("cfg")) {
             continue;
         }
         println!("running snippet {}...", snippet.to_string_lossy());
 
-        let result = std::panic::catch_unwind(|| run_snippet(&snippet));
-        match result {
-            Ok(inner) => {
-                inner?;
-                passed += 1;
+        // A panicking snippet is reported like any other failure, and the rest still run.
+        match std::panic::catch_unwind(|| run_snippet(&snippet)) {
+            Ok(Ok(())) => passed += 1,
+            Ok(Err(e)) => {
+                println!("{FAILED}: {e:#}");
+                failed += 1;
             }
-            Err(_) => {
+            Err(panic) => {
+                let message = panic
+                    .downcast_ref::<&str>()
+                    .copied()
+                    .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+                    .unwrap_or("unknown cause");
+                println!("{FAILED}: panicked: {message}");
                 failed += 1;
             }
         }
     }
     let result = if failed == 0 { OK } else { FAILED };
     println!("\ntest result: {result}. {passed} passed; {failed} failed\n");
+    if failed > 0 {
+        std::process::exit(1);
+    }
     Ok(())
 }